@@ -296,6 +296,12 @@ impl Client {
             ClientSink { sink },
         )
     }
+
+    /// Returns the underlying connection, to send frames not covered by [`SendMessage`].
+    #[cfg(feature = "server")]
+    pub(crate) fn into_conn(self) -> Conn {
+        self.conn
+    }
 }
 
 impl Stream for Client {
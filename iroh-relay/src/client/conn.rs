@@ -252,7 +252,7 @@ impl Sink<Frame> for Conn {
     }
 
     fn start_send(mut self: Pin<&mut Self>, frame: Frame) -> Result<(), Self::Error> {
-        if let Frame::SendPacket { dst_key: _, packet } = &frame {
+        if let Frame::SendPacket { packet, .. } | Frame::ForwardPacket { packet, .. } = &frame {
             if packet.len() > MAX_PACKET_SIZE {
                 return Err(ExceedsMaxPacketSizeSnafu { size: packet.len() }.build());
             }
@@ -10,6 +10,8 @@ pub(crate) const SUPPORTED_WEBSOCKET_VERSION: &str = "13";
 pub const RELAY_PATH: &str = "/relay";
 /// The HTTP path under which the relay allows doing latency queries for testing.
 pub const RELAY_PROBE_PATH: &str = "/ping";
+/// The HTTP path under which the relay server reports its health for monitoring.
+pub const HEALTHZ_PATH: &str = "/healthz";
 /// The legacy HTTP path under which the relay used to accept relaying connections.
 /// We keep this for backwards compatibility.
 #[cfg(feature = "server")] // legacy paths only used on server-side for backwards compat
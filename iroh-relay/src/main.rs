@@ -12,14 +12,13 @@ use std::{
 
 use clap::Parser;
 use http::StatusCode;
-use iroh_base::NodeId;
+use iroh_base::{NodeId, RelayUrl, SecretKey};
 use iroh_relay::{
     defaults::{
         DEFAULT_HTTPS_PORT, DEFAULT_HTTP_PORT, DEFAULT_METRICS_PORT, DEFAULT_RELAY_QUIC_PORT,
     },
     server::{self as relay, ClientRateLimit, QuicConfig},
 };
-use n0_future::FutureExt;
 use n0_snafu::{Error, Result, ResultExt};
 use serde::{Deserialize, Serialize};
 use snafu::whatever;
@@ -167,6 +166,38 @@ struct Config {
     /// This controls which nodes are allowed to relay connections, other endpoints are not controlled by this.
     #[serde(default)]
     access: AccessConfig,
+    /// Mesh federation with other relay servers.
+    ///
+    /// Packets for nodes not connected to this relay server are forwarded to the mesh
+    /// peer the node is connected to.  Disabled if not present.
+    mesh: Option<MeshConfig>,
+    /// The socket address to bind the admin HTTP service on.
+    ///
+    /// Serves statistics about the connected clients as JSON on `/clients`.  This is
+    /// served without authentication, only bind it to a private address.  Disabled if not
+    /// present.
+    admin_bind_addr: Option<SocketAddr>,
+}
+
+/// Mesh federation configuration.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct MeshConfig {
+    /// The secret key used to authenticate to the mesh peers, hex encoded.
+    ///
+    /// The node id of this key must be configured as a mesh peer on the other relay servers.
+    secret_key: String,
+    /// The other relay servers of the mesh.
+    #[serde(default)]
+    peers: Vec<MeshPeer>,
+}
+
+/// A relay server in the mesh.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct MeshPeer {
+    /// The URL of the relay server.
+    url: Url,
+    /// The node id the relay server authenticates with.
+    node_id: NodeId,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
@@ -207,32 +238,10 @@ impl From<AccessConfig> for iroh_relay::server::AccessConfig {
         match cfg {
             AccessConfig::Everyone => iroh_relay::server::AccessConfig::Everyone,
             AccessConfig::Allowlist(allow_list) => {
-                let allow_list = Arc::new(allow_list);
-                iroh_relay::server::AccessConfig::Restricted(Box::new(move |node_id| {
-                    let allow_list = allow_list.clone();
-                    async move {
-                        if allow_list.contains(&node_id) {
-                            iroh_relay::server::Access::Allow
-                        } else {
-                            iroh_relay::server::Access::Deny
-                        }
-                    }
-                    .boxed()
-                }))
+                iroh_relay::server::AccessConfig::allowlist(allow_list)
             }
             AccessConfig::Denylist(deny_list) => {
-                let deny_list = Arc::new(deny_list);
-                iroh_relay::server::AccessConfig::Restricted(Box::new(move |node_id| {
-                    let deny_list = deny_list.clone();
-                    async move {
-                        if deny_list.contains(&node_id) {
-                            iroh_relay::server::Access::Deny
-                        } else {
-                            iroh_relay::server::Access::Allow
-                        }
-                    }
-                    .boxed()
-                }))
+                iroh_relay::server::AccessConfig::denylist(deny_list)
             }
             AccessConfig::Http(mut config) => {
                 let client = reqwest::Client::default();
@@ -241,11 +250,11 @@ impl From<AccessConfig> for iroh_relay::server::AccessConfig {
                     config.bearer_token = Some(token);
                 }
                 let config = Arc::new(config);
-                iroh_relay::server::AccessConfig::Restricted(Box::new(move |node_id| {
+                iroh_relay::server::AccessConfig::restricted(move |node_id| {
                     let client = client.clone();
                     let config = config.clone();
-                    async move { http_access_check(&client, &config, node_id).await }.boxed()
-                }))
+                    async move { http_access_check(&client, &config, node_id).await }
+                })
             }
         }
     }
@@ -322,6 +331,8 @@ impl Default for Config {
             metrics_bind_addr: None,
             key_cache_capacity: Default::default(),
             access: AccessConfig::Everyone,
+            mesh: None,
+            admin_bind_addr: None,
         }
     }
 }
@@ -453,6 +464,11 @@ struct Limits {
     accept_conn_limit: Option<f64>,
     /// Burst limit for accepting new connection. Unlimited if not set.
     accept_conn_burst: Option<usize>,
+    /// Maximum number of concurrently connected clients. Unlimited if not set.
+    max_clients: Option<usize>,
+    /// Maximum number of concurrent connections from a single client key. Unlimited if
+    /// not set.
+    max_connections_per_client: Option<usize>,
     /// Rate limiting configuration per client.
     client: Option<PerClientRateLimitConfig>,
 }
@@ -476,6 +492,8 @@ struct RateLimitConfig {
     bytes_per_second: Option<u32>,
     /// Maximum number of bytes to read in a single burst.
     max_burst_bytes: Option<u32>,
+    /// Maximum number of frames per second.
+    frames_per_second: Option<u32>,
 }
 
 impl Config {
@@ -677,6 +695,13 @@ async fn build_relay_config(cfg: Config) -> Result<relay::ServerConfig<std::io::
                                         .context("max_burst_bytes must be non-zero u32")
                                 })
                                 .transpose()?,
+                            frames_per_second: rx
+                                .frames_per_second
+                                .map(|v| {
+                                    TryInto::<NonZeroU32>::try_into(v)
+                                        .context("frames_per_second must be non-zero u32")
+                                })
+                                .transpose()?,
                         }),
                         None => None,
                     }
@@ -687,10 +712,29 @@ async fn build_relay_config(cfg: Config) -> Result<relay::ServerConfig<std::io::
                 accept_conn_limit: limits.accept_conn_limit,
                 accept_conn_burst: limits.accept_conn_burst,
                 client_rx,
+                max_clients: limits.max_clients,
+                max_connections_per_client: limits.max_connections_per_client,
             }
         }
         None => Default::default(),
     };
+    let mesh = match cfg.mesh {
+        Some(ref mesh) => Some(relay::MeshConfig {
+            secret_key: mesh
+                .secret_key
+                .parse::<SecretKey>()
+                .context("invalid mesh secret_key")?,
+            peers: mesh
+                .peers
+                .iter()
+                .map(|peer| relay::MeshPeer {
+                    url: RelayUrl::from(peer.url.clone()),
+                    node_id: peer.node_id,
+                })
+                .collect(),
+        }),
+        None => None,
+    };
 
     let relay_config = relay::RelayConfig {
         http_bind_addr: cfg.http_bind_addr(),
@@ -699,6 +743,8 @@ async fn build_relay_config(cfg: Config) -> Result<relay::ServerConfig<std::io::
         limits,
         key_cache_capacity: cfg.key_cache_capacity,
         access: cfg.access.clone().into(),
+        mesh,
+        admin_bind_addr: cfg.admin_bind_addr,
     };
 
     Ok(relay::ServerConfig {
@@ -723,9 +769,13 @@ mod tests {
     #[tokio::test]
     async fn test_rate_limit_config() -> Result {
         let config = "
+            [limits]
+            max_clients = 1000
+
             [limits.client.rx]
             bytes_per_second = 400
             max_burst_bytes = 800
+            frames_per_second = 20
         ";
         let config = Config::from_str(config)?;
         let relay_config = build_relay_config(config).await?;
@@ -739,6 +789,11 @@ mod tests {
             relay.limits.client_rx.expect("ratelimit").max_burst_bytes,
             Some(NonZeroU32::try_from(800).unwrap())
         );
+        assert_eq!(
+            relay.limits.client_rx.expect("ratelimit").frames_per_second,
+            Some(NonZeroU32::try_from(20).unwrap())
+        );
+        assert_eq!(relay.limits.max_clients, Some(1000));
 
         Ok(())
     }
@@ -750,6 +805,43 @@ mod tests {
 
         let relay = relay_config.relay.expect("no relay config");
         assert!(relay.limits.client_rx.is_none());
+        assert!(relay.limits.max_clients.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mesh_config() -> Result {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mesh_key = SecretKey::generate(&mut rng);
+        let peer_id = SecretKey::generate(&mut rng).public();
+        let config = format!(
+            "
+            [mesh]
+            secret_key = \"{}\"
+
+            [[mesh.peers]]
+            url = \"https://relay2.example.com\"
+            node_id = \"{peer_id}\"
+        ",
+            data_encoding::HEXLOWER.encode(&mesh_key.to_bytes())
+        );
+        let config = Config::from_str(&config)?;
+        let relay_config = build_relay_config(config).await?;
+
+        let mesh = relay_config
+            .relay
+            .expect("no relay config")
+            .mesh
+            .expect("no mesh config");
+        assert_eq!(mesh.secret_key.public(), mesh_key.public());
+        assert_eq!(
+            mesh.peers,
+            vec![relay::MeshPeer {
+                url: "https://relay2.example.com".parse()?,
+                node_id: peer_id,
+            }]
+        );
 
         Ok(())
     }
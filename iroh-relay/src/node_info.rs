@@ -25,6 +25,11 @@
 //! - `addr=<addr> <addr>`: A space-separated list of sockets addresses for this iroh node.
 //!   Each address is an IPv4 or IPv6 address with a port.
 //!
+//! - `user-data=<data>`: The application-defined [`UserData`] of this node.
+//!
+//! - `attr=<key>=<value>`: An application-defined attribute of this node, see
+//!   [`Attributes`].  There is one TXT record per attribute.
+//!
 //! [Pkarr]: https://app.pkarr.org
 //! [z-base-32]: https://philzimmermann.com/docs/human-oriented-base-32-encoding.txt
 //! [RFC1464]: https://www.rfc-editor.org/rfc/rfc1464
@@ -46,7 +51,7 @@ use iroh_base::{NodeAddr, NodeId, RelayUrl, SecretKey, SignatureError};
 use nested_enum_utils::common_fields;
 use snafu::{Backtrace, ResultExt, Snafu};
 #[cfg(not(wasm_browser))]
-use tracing::warn;
+use tracing::{debug, warn};
 use url::Url;
 
 #[cfg(not(wasm_browser))]
@@ -125,9 +130,9 @@ impl NodeIdExt for NodeId {
 
 /// Data about a node that may be published to and resolved from discovery services.
 ///
-/// This includes an optional [`RelayUrl`], a set of direct addresses, and the optional
+/// This includes an optional [`RelayUrl`], a set of direct addresses, the optional
 /// [`UserData`], a string that can be set by applications and is not parsed or used by iroh
-/// itself.
+/// itself, and application-defined [`Attributes`].
 ///
 /// This struct does not include the node's [`NodeId`], only the data *about* a certain
 /// node. See [`NodeInfo`] for a struct that contains a [`NodeId`] with associated [`NodeData`].
@@ -139,6 +144,8 @@ pub struct NodeData {
     direct_addresses: BTreeSet<SocketAddr>,
     /// Optional user-defined [`UserData`] for this node.
     user_data: Option<UserData>,
+    /// Application-defined [`Attributes`] for this node.
+    attributes: Attributes,
 }
 
 impl NodeData {
@@ -148,6 +155,7 @@ impl NodeData {
             relay_url,
             direct_addresses,
             user_data: None,
+            attributes: Default::default(),
         }
     }
 
@@ -169,6 +177,12 @@ impl NodeData {
         self
     }
 
+    /// Sets the application-defined attributes and returns the updated node data.
+    pub fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
     /// Returns the relay URL of the node.
     pub fn relay_url(&self) -> Option<&RelayUrl> {
         self.relay_url.as_ref()
@@ -179,6 +193,11 @@ impl NodeData {
         self.user_data.as_ref()
     }
 
+    /// Returns the application-defined attributes of the node.
+    pub fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
     /// Returns the direct addresses of the node.
     pub fn direct_addresses(&self) -> &BTreeSet<SocketAddr> {
         &self.direct_addresses
@@ -203,6 +222,11 @@ impl NodeData {
     pub fn set_user_data(&mut self, user_data: Option<UserData>) {
         self.user_data = user_data;
     }
+
+    /// Sets the application-defined attributes of the node data.
+    pub fn set_attributes(&mut self, attributes: Attributes) {
+        self.attributes = attributes;
+    }
 }
 
 impl From<NodeAddr> for NodeData {
@@ -211,6 +235,7 @@ impl From<NodeAddr> for NodeData {
             relay_url: node_addr.relay_url,
             direct_addresses: node_addr.direct_addresses,
             user_data: None,
+            attributes: Default::default(),
         }
     }
 }
@@ -274,6 +299,154 @@ impl AsRef<str> for UserData {
     }
 }
 
+/// Application-defined key/value attributes that can be published and resolved through
+/// node discovery.
+///
+/// Attributes allow applications to describe a node, e.g. with a service name, a version or
+/// its capabilities, so that discovered nodes can be filtered before dialing them.  Like
+/// all published node data they are signed by the node's key when published via pkarr.
+///
+/// Each attribute is published as its own `attr=<key>=<value>` TXT record, so the key and
+/// value together may be at most [`Attributes::MAX_ENTRY_LENGTH`] bytes.  As pkarr packets
+/// are limited to 1000 bytes, there may be at most [`Attributes::MAX_COUNT`] attributes
+/// whose encoded records take up at most [`Attributes::MAX_TOTAL_LENGTH`] bytes, leaving
+/// room for the relay URL, direct addresses and user-data.  Keys must be non-empty,
+/// printable ASCII and may not contain `=`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Attributes(BTreeMap<String, String>);
+
+impl Attributes {
+    /// The max byte length of the key and value of a single attribute.
+    ///
+    /// A TXT record character string has a max length of 255 bytes, minus the `attr=`
+    /// prefix and the `=` separating key and value.
+    pub const MAX_ENTRY_LENGTH: usize = 249;
+
+    /// The max number of attributes.
+    pub const MAX_COUNT: usize = 16;
+
+    /// The max total byte length of the encoded attribute records.
+    ///
+    /// Each attribute counts with the length of its key and value plus
+    /// [`Attributes::ENTRY_OVERHEAD`].
+    pub const MAX_TOTAL_LENGTH: usize = 500;
+
+    /// The bytes a DNS TXT record adds to the key and value of an attribute.
+    ///
+    /// This is the record name, type, class, TTL and data length, the length of the
+    /// character string and the `attr=` prefix and `=` separator.
+    pub const ENTRY_OVERHEAD: usize = 24;
+
+    /// Creates empty attributes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts an attribute, returning the previous value of the key.
+    ///
+    /// Fails if the key is invalid or the size limits are exceeded, in which case the
+    /// attributes are not modified.
+    pub fn insert(
+        &mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Option<String>, AttributeError> {
+        let key = key.into();
+        let value = value.into();
+        snafu::ensure!(
+            !key.is_empty() && key.bytes().all(|b| b.is_ascii_graphic() && b != b'='),
+            InvalidKeySnafu { key }
+        );
+        snafu::ensure!(
+            key.len() + value.len() <= Self::MAX_ENTRY_LENGTH,
+            EntryTooLongSnafu { key }
+        );
+        let previous_len = self.0.get(&key).map(|v| Self::encoded_len(&key, v));
+        snafu::ensure!(
+            previous_len.is_some() || self.0.len() < Self::MAX_COUNT,
+            TooManySnafu
+        );
+        snafu::ensure!(
+            self.total_len() - previous_len.unwrap_or(0) + Self::encoded_len(&key, &value)
+                <= Self::MAX_TOTAL_LENGTH,
+            TotalTooLongSnafu
+        );
+        Ok(self.0.insert(key, value))
+    }
+
+    /// Inserts an attribute and returns the updated attributes.
+    pub fn with(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self, AttributeError> {
+        self.insert(key, value)?;
+        Ok(self)
+    }
+
+    /// Returns the value of an attribute.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Removes an attribute, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    /// Returns an iterator over the attributes, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the number of attributes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no attributes.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn total_len(&self) -> usize {
+        self.0.iter().map(|(k, v)| Self::encoded_len(k, v)).sum()
+    }
+
+    fn encoded_len(key: &str, value: &str) -> usize {
+        key.len() + value.len() + Self::ENTRY_OVERHEAD
+    }
+
+    /// Parses an attribute from its `<key>=<value>` TXT representation.
+    ///
+    /// Invalid attributes are skipped.
+    fn insert_encoded(&mut self, s: &str) {
+        if let Some((key, value)) = s.split_once('=') {
+            self.insert(key, value).ok();
+        }
+    }
+}
+
+/// Error returned when inserting an invalid attribute into [`Attributes`].
+#[common_fields({
+    backtrace: Option<Backtrace>,
+    #[snafu(implicit)]
+    span_trace: n0_snafu::SpanTrace,
+})]
+#[allow(missing_docs)]
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum AttributeError {
+    #[snafu(display("Invalid attribute key `{key}`"))]
+    InvalidKey { key: String },
+    #[snafu(display("Attribute `{key}` exceeds the max length"))]
+    EntryTooLong { key: String },
+    #[snafu(display("Attributes exceed the max total length"))]
+    TotalTooLong {},
+    #[snafu(display("Too many attributes"))]
+    TooMany {},
+}
+
 /// Information about a node that may be published to and resolved from discovery services.
 ///
 /// This struct couples a [`NodeId`] with its associated [`NodeData`].
@@ -313,10 +486,15 @@ impl From<&TxtAttrs<IrohAttr>> for NodeInfo {
             .flatten()
             .next()
             .and_then(|s| UserData::from_str(s).ok());
+        let mut attributes = Attributes::new();
+        for s in attrs.get(&IrohAttr::Attr).into_iter().flatten() {
+            attributes.insert_encoded(s);
+        }
         let data = NodeData {
             relay_url: relay_url.map(Into::into),
             direct_addresses,
             user_data,
+            attributes,
         };
         Self { node_id, data }
     }
@@ -365,6 +543,12 @@ impl NodeInfo {
         self
     }
 
+    /// Sets the application-defined attributes and returns the updated node info.
+    pub fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.data = self.data.with_attributes(attributes);
+        self
+    }
+
     /// Converts into a [`NodeAddr`] by cloning the needed fields.
     pub fn to_node_addr(&self) -> NodeAddr {
         NodeAddr {
@@ -455,8 +639,6 @@ pub enum LookupError {
 pub enum ParseError {
     #[snafu(display("Expected format `key=value`, received `{s}`"))]
     UnexpectedFormat { s: String },
-    #[snafu(display("Could not convert key to Attr"))]
-    AttrFromString { key: String },
     #[snafu(display("Expected 2 labels, received {num_labels}"))]
     NumLabels { num_labels: usize },
     #[snafu(display("Could not parse labels"))]
@@ -524,6 +706,8 @@ pub(crate) enum IrohAttr {
     Addr,
     /// User-defined data
     UserData,
+    /// Application-defined attribute
+    Attr,
 }
 
 /// Attributes parsed from [`IROH_TXT_NAME`] TXT records.
@@ -549,6 +733,9 @@ impl From<&NodeInfo> for TxtAttrs<IrohAttr> {
         if let Some(user_data) = &info.data.user_data {
             attrs.push((IrohAttr::UserData, user_data.to_string()));
         }
+        for (key, value) in info.data.attributes.iter() {
+            attrs.push((IrohAttr::Attr, format!("{key}={value}")));
+        }
         Self::from_parts(info.node_id, attrs.into_iter())
     }
 }
@@ -564,17 +751,22 @@ impl<T: FromStr + Display + Hash + Ord> TxtAttrs<T> {
     }
 
     /// Creates [`TxtAttrs`] from a node id and an iterator of "{key}={value}" strings.
+    ///
+    /// Unknown keys are skipped, so that records published by newer versions can still
+    /// be parsed.
     pub(crate) fn from_strings(
         node_id: NodeId,
         strings: impl Iterator<Item = String>,
     ) -> Result<Self, ParseError> {
         let mut attrs: BTreeMap<T, Vec<String>> = BTreeMap::new();
         for s in strings {
-            let mut parts = s.split('=');
-            let (Some(key), Some(value)) = (parts.next(), parts.next()) else {
+            let Some((key, value)) = s.split_once('=') else {
                 return Err(UnexpectedFormatSnafu { s }.build());
             };
-            let attr = T::from_str(key).map_err(|_| AttrFromStringSnafu { key }.build())?;
+            let Ok(attr) = T::from_str(key) else {
+                debug!(%node_id, %key, "skipping unknown TXT attribute");
+                continue;
+            };
             attrs.entry(attr).or_default().push(value.to_string());
         }
         Ok(Self { attrs, node_id })
@@ -750,10 +942,10 @@ mod tests {
         },
         Name,
     };
-    use iroh_base::{NodeId, SecretKey};
+    use iroh_base::{NodeId, RelayUrl, SecretKey};
     use n0_snafu::{Result, ResultExt};
 
-    use super::{NodeData, NodeIdExt, NodeInfo};
+    use super::{Attributes, IrohAttr, NodeData, NodeIdExt, NodeInfo, TxtAttrs, UserData};
 
     #[test]
     fn txt_attr_roundtrip() {
@@ -779,13 +971,107 @@ mod tests {
             Some("https://example.com".parse().unwrap()),
             ["127.0.0.1:1234".parse().unwrap()].into_iter().collect(),
         )
-        .with_user_data(Some("foobar".parse().unwrap()));
+        .with_user_data(Some("foobar".parse().unwrap()))
+        .with_attributes(
+            Attributes::new()
+                .with("service", "chat")
+                .unwrap()
+                .with("query", "a=b")
+                .unwrap(),
+        );
         let expected = NodeInfo::from_parts(secret_key.public(), node_data);
         let packet = expected.to_pkarr_signed_packet(&secret_key, 30).unwrap();
         let actual = NodeInfo::from_pkarr_signed_packet(&packet).unwrap();
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn txt_attr_unknown_keys() {
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let strings = [
+            "relay=https://example.com/",
+            "unknown=value",
+            "addr=127.0.0.1:1234",
+        ];
+        let attrs =
+            TxtAttrs::<IrohAttr>::from_strings(node_id, strings.into_iter().map(String::from))
+                .unwrap();
+        let info = NodeInfo::from(&attrs);
+        assert_eq!(
+            info.relay_url(),
+            Some(&"https://example.com".parse().unwrap())
+        );
+        assert_eq!(
+            info.direct_addresses(),
+            &BTreeSet::from(["127.0.0.1:1234".parse().unwrap()])
+        );
+    }
+
+    #[test]
+    fn attributes_limits() {
+        let mut attrs = Attributes::new();
+        assert!(attrs.insert("", "value").is_err());
+        assert!(attrs.insert("a=b", "value").is_err());
+        assert!(attrs.insert("with space", "value").is_err());
+        assert!(attrs
+            .insert("key", "x".repeat(Attributes::MAX_ENTRY_LENGTH))
+            .is_err());
+
+        let value = "x".repeat(Attributes::MAX_ENTRY_LENGTH - 2);
+        attrs.insert("k1", value.clone()).unwrap();
+        assert!(attrs.insert("k2", value.clone()).is_err());
+        // Replacing an attribute only counts the new value.
+        assert_eq!(attrs.insert("k1", "short").unwrap(), Some(value));
+        assert_eq!(attrs.get("k1"), Some("short"));
+        assert_eq!(attrs.len(), 1);
+
+        let mut attrs = Attributes::new();
+        for i in 0..Attributes::MAX_COUNT {
+            attrs.insert(format!("k{i:02}"), "x").unwrap();
+        }
+        assert!(attrs.insert("k99", "x").is_err());
+        // Replacing does not count as an additional attribute.
+        attrs.insert("k00", "y").unwrap();
+        assert_eq!(attrs.len(), Attributes::MAX_COUNT);
+    }
+
+    #[test]
+    fn attributes_max_size_signed_packet() -> Result {
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let relay_url: RelayUrl = "https://euw1-1.relay.iroh.network./".parse()?;
+        let user_data = UserData::try_from("u".repeat(UserData::MAX_LENGTH))?;
+
+        // Few large attributes.
+        let mut large = Attributes::new();
+        let value_len = Attributes::MAX_ENTRY_LENGTH - 2;
+        large.insert("k1", "x".repeat(value_len))?;
+        let remaining = Attributes::MAX_TOTAL_LENGTH - large.total_len();
+        large.insert("k2", "x".repeat(remaining - Attributes::ENTRY_OVERHEAD - 2))?;
+        assert_eq!(large.total_len(), Attributes::MAX_TOTAL_LENGTH);
+
+        // Many small attributes.
+        let mut many = Attributes::new();
+        let entry_len = Attributes::MAX_TOTAL_LENGTH / Attributes::MAX_COUNT;
+        for i in 0..Attributes::MAX_COUNT {
+            let value = "x".repeat(entry_len - Attributes::ENTRY_OVERHEAD - 3);
+            many.insert(format!("k{i:02}"), value)?;
+        }
+
+        for attributes in [large, many] {
+            let node_data = NodeData::new(
+                Some(relay_url.clone()),
+                ["127.0.0.1:1234".parse().unwrap()].into_iter().collect(),
+            )
+            .with_user_data(Some(user_data.clone()))
+            .with_attributes(attributes);
+            let info = NodeInfo::from_parts(secret_key.public(), node_data);
+            let packet = info.to_pkarr_signed_packet(&secret_key, 30)?;
+            let parsed = NodeInfo::from_pkarr_signed_packet(&packet)?;
+            assert_eq!(parsed, info);
+        }
+        Ok(())
+    }
+
     /// There used to be a bug where uploading a NodeAddr with more than only exactly
     /// one relay URL or one publicly reachable IP addr would prevent connection
     /// establishment.
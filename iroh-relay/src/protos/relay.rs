@@ -93,8 +93,13 @@ pub enum FrameType {
     ///
     /// 32B pub key of peer that's gone
     PeerGone = 8,
-    /// Frames 9-11 concern meshing, which we have eliminated from our version of the protocol.
-    /// Messages with these frames will be ignored.
+    /// Sent between meshed relay servers to forward a packet to a client connected to the
+    /// receiving relay server.
+    ///
+    /// 32B src pub key + 32B dst pub key + packet bytes
+    ForwardPacket = 10,
+    /// Frames 9 and 11 concern DERP style meshing, which we have eliminated from our version
+    /// of the protocol.  Messages with these frames will be ignored.
     /// 8 byte ping payload, to be echoed back in FrameType::Pong
     Ping = 12,
     /// 8 byte payload, the contents of ping being replied to
@@ -111,6 +116,11 @@ pub enum FrameType {
     ///
     /// Handled on the `[relay::Client]`, but currently never sent on the `[relay::Server]`
     Restarting = 15,
+    /// Sent between meshed relay servers to announce whether a client is connected to the
+    /// sending relay server.
+    ///
+    /// 32B pub key of the client + 1 byte payload: 0x01 or 0x00 for whether it is connected
+    MeshPresence = 16,
     /// Unknown frame type
     #[num_enum(default)]
     Unknown = 255,
@@ -322,6 +332,15 @@ pub(crate) enum Frame {
         reconnect_in: u32,
         try_for: u32,
     },
+    ForwardPacket {
+        src_key: PublicKey,
+        dst_key: PublicKey,
+        packet: Bytes,
+    },
+    MeshPresence {
+        node_id: PublicKey,
+        present: bool,
+    },
 }
 
 impl Frame {
@@ -337,6 +356,8 @@ impl Frame {
             Frame::Pong { .. } => FrameType::Pong,
             Frame::Health { .. } => FrameType::Health,
             Frame::Restarting { .. } => FrameType::Restarting,
+            Frame::ForwardPacket { .. } => FrameType::ForwardPacket,
+            Frame::MeshPresence { .. } => FrameType::MeshPresence,
         }
     }
 
@@ -361,6 +382,8 @@ impl Frame {
             Frame::Pong { .. } => 8,
             Frame::Health { problem } => problem.len(),
             Frame::Restarting { .. } => 4 + 4,
+            Frame::ForwardPacket { packet, .. } => PublicKey::LENGTH * 2 + packet.len(),
+            Frame::MeshPresence { .. } => PublicKey::LENGTH + 1,
         }
     }
 
@@ -441,6 +464,19 @@ impl Frame {
                 dst.put_u32(*reconnect_in);
                 dst.put_u32(*try_for);
             }
+            Frame::ForwardPacket {
+                src_key,
+                dst_key,
+                packet,
+            } => {
+                dst.put(src_key.as_ref());
+                dst.put(dst_key.as_ref());
+                dst.put(packet.as_ref());
+            }
+            Frame::MeshPresence { node_id, present } => {
+                dst.put(node_id.as_ref());
+                dst.put_u8(u8::from(*present));
+            }
         }
     }
 
@@ -560,6 +596,38 @@ impl Frame {
                     try_for,
                 }
             }
+            FrameType::ForwardPacket => {
+                if content.len() < PublicKey::LENGTH * 2 {
+                    return Err(InvalidFrameSnafu.build());
+                }
+
+                let frame_len = content.len() - PublicKey::LENGTH * 2;
+                if frame_len > MAX_PACKET_SIZE {
+                    return Err(FrameTooLargeSnafu { frame_len }.build());
+                }
+
+                let src_key = cache.key_from_slice(&content[..PublicKey::LENGTH])?;
+                let dst_key =
+                    cache.key_from_slice(&content[PublicKey::LENGTH..PublicKey::LENGTH * 2])?;
+                let packet = content.slice(PublicKey::LENGTH * 2..);
+                Self::ForwardPacket {
+                    src_key,
+                    dst_key,
+                    packet,
+                }
+            }
+            FrameType::MeshPresence => {
+                if content.len() != PublicKey::LENGTH + 1 {
+                    return Err(InvalidFrameSnafu.build());
+                }
+                let node_id = cache.key_from_slice(&content[..PublicKey::LENGTH])?;
+                let present = match content[PublicKey::LENGTH] {
+                    1 => true,
+                    0 => false,
+                    _ => return Err(InvalidFrameSnafu.build()),
+                };
+                Self::MeshPresence { node_id, present }
+            }
             _ => {
                 return Err(InvalidFrameTypeSnafu { frame_type }.build());
             }
@@ -804,6 +872,27 @@ mod tests {
                 },
                 "0f 00 00 00 0a 00 00 00 14",
             ),
+            (
+                Frame::ForwardPacket {
+                    src_key: client_key.public(),
+                    dst_key: client_key.public(),
+                    packet: "Hi!".into(),
+                },
+                "0a 19 7f 6b 23 e1 6c 85 32 c6 ab c8 38 fa cd 5e
+                a7 89 be 0c 76 b2 92 03 34 03 9b fa 8b 3d 36 8d
+                61 19 7f 6b 23 e1 6c 85 32 c6 ab c8 38 fa cd 5e
+                a7 89 be 0c 76 b2 92 03 34 03 9b fa 8b 3d 36 8d
+                61 48 69 21",
+            ),
+            (
+                Frame::MeshPresence {
+                    node_id: client_key.public(),
+                    present: true,
+                },
+                "10 19 7f 6b 23 e1 6c 85 32 c6 ab c8 38 fa cd 5e
+                a7 89 be 0c 76 b2 92 03 34 03 9b fa 8b 3d 36 8d
+                61 01",
+            ),
         ];
 
         for (frame, expected_hex) in frames {
@@ -877,6 +966,14 @@ mod proptests {
                 reconnect_in,
                 try_for,
             });
+        let forward_packet =
+            (key(), key(), data(64)).prop_map(|(src_key, dst_key, packet)| Frame::ForwardPacket {
+                src_key,
+                dst_key,
+                packet,
+            });
+        let mesh_presence = (key(), any::<bool>())
+            .prop_map(|(node_id, present)| Frame::MeshPresence { node_id, present });
         prop_oneof![
             client_info,
             send_packet,
@@ -888,6 +985,8 @@ mod proptests {
             pong,
             health,
             restarting,
+            forward_packet,
+            mesh_presence,
         ]
     }
 
@@ -899,11 +998,13 @@ mod proptests {
                 | FrameType::Ping
                 | FrameType::Pong
                 | FrameType::Restarting
-                | FrameType::PeerGone => true,
+                | FrameType::PeerGone
+                | FrameType::MeshPresence => true,
                 FrameType::ClientInfo
                 | FrameType::Health
                 | FrameType::SendPacket
                 | FrameType::RecvPacket
+                | FrameType::ForwardPacket
                 | FrameType::Unknown => false,
             }
         }
@@ -27,6 +27,7 @@ pub(crate) mod server {
     use tracing::{debug, info, info_span, Instrument};
 
     use super::*;
+    use crate::server::Metrics;
     pub use crate::server::QuicConfig;
 
     pub struct QuicServer {
@@ -100,7 +101,10 @@ pub(crate) mod server {
         /// If there is a panic during a connection, it will be propagated
         /// up here. Any other errors in a connection will be logged as a
         ///  warning.
-        pub(crate) fn spawn(mut quic_config: QuicConfig) -> Result<Self, QuicSpawnError> {
+        pub(crate) fn spawn(
+            mut quic_config: QuicConfig,
+            metrics: Arc<Metrics>,
+        ) -> Result<Self, QuicSpawnError> {
             quic_config.server_config.alpn_protocols =
                 vec![crate::quic::ALPN_QUIC_ADDR_DISC.to_vec()];
             let server_config = QuicServerConfig::try_from(quic_config.server_config)?;
@@ -144,6 +148,7 @@ pub(crate) mod server {
                             res = endpoint.accept() => match res {
                                 Some(conn) => {
                                      debug!("accepting connection");
+                                     metrics.qad_conns.inc();
                                      let remote_addr = conn.remote_address();
                                      set.spawn(
                                          handle_connection(conn).instrument(info_span!("qad-conn", %remote_addr))
@@ -380,10 +385,13 @@ mod tests {
         // create a server config with self signed certificates
         let (_, server_config) = super::super::server::testing::self_signed_tls_certs_and_config();
         let bind_addr = SocketAddr::new(host.into(), 0);
-        let quic_server = QuicServer::spawn(QuicConfig {
-            server_config,
-            bind_addr,
-        })?;
+        let quic_server = QuicServer::spawn(
+            QuicConfig {
+                server_config,
+                bind_addr,
+            },
+            Default::default(),
+        )?;
 
         // create a client-side endpoint
         let client_endpoint =
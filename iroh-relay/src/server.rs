@@ -14,8 +14,15 @@
 //! - HTTPS `/relay`: The main URL endpoint to which clients connect and sends traffic over.
 //! - HTTPS `/ping`: Used for net_report probes.
 //! - HTTPS `/generate_204`: Used for net_report probes.
+//! - HTTPS `/healthz`: Health check for load balancers and monitoring.
+//!
+//! If configured with `ServerConfig::metrics_addr`, metrics are served in the Prometheus
+//! format on `/metrics` of a separate HTTP server.
 
-use std::{fmt, future::Future, net::SocketAddr, num::NonZeroU32, pin::Pin, sync::Arc};
+use std::{
+    collections::HashSet, fmt, future::Future, net::SocketAddr, num::NonZeroU32, pin::Pin,
+    sync::Arc,
+};
 
 use derive_more::Debug;
 use http::{
@@ -28,6 +35,7 @@ use iroh_base::NodeId;
 use iroh_base::RelayUrl;
 use n0_future::{future::Boxed, StreamExt};
 use nested_enum_utils::common_fields;
+use serde::Serialize;
 use snafu::{Backtrace, ResultExt, Snafu};
 use tokio::{
     net::TcpListener,
@@ -38,13 +46,14 @@ use tracing::{debug, error, info, info_span, instrument, Instrument};
 
 use crate::{
     defaults::DEFAULT_KEY_CACHE_CAPACITY,
-    http::RELAY_PROBE_PATH,
+    http::{HEALTHZ_PATH, RELAY_PROBE_PATH},
     quic::server::{QuicServer, QuicSpawnError, ServerHandle as QuicServerHandle},
 };
 
 mod client;
 mod clients;
 mod http_server;
+mod mesh;
 mod metrics;
 pub(crate) mod resolver;
 pub(crate) mod streams;
@@ -52,6 +61,7 @@ pub(crate) mod streams;
 pub mod testing;
 
 pub use self::{
+    mesh::{MeshConfig, MeshPeer},
     metrics::{Metrics, RelayMetrics},
     resolver::{ReloadingResolver, DEFAULT_CERT_RELOAD_INTERVAL},
 };
@@ -126,6 +136,17 @@ pub struct RelayConfig<EC: fmt::Debug, EA: fmt::Debug = EC> {
     pub key_cache_capacity: Option<usize>,
     /// Access configuration.
     pub access: AccessConfig,
+    /// Mesh configuration, federating this relay server with other relay servers.
+    ///
+    /// Packets for nodes which are not connected to this relay server are forwarded to
+    /// the mesh peer the node is connected to.  Disabled if `None`.
+    pub mesh: Option<MeshConfig>,
+    /// Socket to serve the admin HTTP service on, disabled if `None`.
+    ///
+    /// The admin service serves the [`ClientStats`] of the connected clients as JSON on
+    /// `/clients`, the heaviest talkers first.  It is served without any authentication
+    /// so should only be bound to a private address.
+    pub admin_bind_addr: Option<SocketAddr>,
 }
 
 /// Controls which nodes are allowed to use the relay.
@@ -139,6 +160,49 @@ pub enum AccessConfig {
 }
 
 impl AccessConfig {
+    /// Only allows nodes for which the `check` function resolves to [`Access::Allow`].
+    ///
+    /// This is a convenience for creating [`AccessConfig::Restricted`] from an async
+    /// function, it is called for every connecting node, e.g. to consult an external
+    /// authentication service.
+    pub fn restricted<F, Fut>(check: F) -> Self
+    where
+        F: Fn(NodeId) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Access> + Send + 'static,
+    {
+        Self::Restricted(Box::new(move |node_id| Box::pin(check(node_id))))
+    }
+
+    /// Only allows the given nodes.
+    pub fn allowlist(nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        let nodes: Arc<HashSet<NodeId>> = Arc::new(nodes.into_iter().collect());
+        Self::restricted(move |node_id| {
+            let allowed = nodes.contains(&node_id);
+            async move {
+                if allowed {
+                    Access::Allow
+                } else {
+                    Access::Deny
+                }
+            }
+        })
+    }
+
+    /// Allows all nodes, except for the given nodes.
+    pub fn denylist(nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        let nodes: Arc<HashSet<NodeId>> = Arc::new(nodes.into_iter().collect());
+        Self::restricted(move |node_id| {
+            let denied = nodes.contains(&node_id);
+            async move {
+                if denied {
+                    Access::Deny
+                } else {
+                    Access::Allow
+                }
+            }
+        })
+    }
+
     /// Is this node allowed?
     pub async fn is_allowed(&self, node: NodeId) -> bool {
         match self {
@@ -205,6 +269,20 @@ pub struct Limits {
     pub accept_conn_burst: Option<usize>,
     /// Rate limits for incoming traffic from a client connection.
     pub client_rx: Option<ClientRateLimit>,
+    /// Maximum number of concurrently connected clients. Unlimited if not set.
+    ///
+    /// Once reached, new clients are rejected until other clients disconnect.  A client
+    /// reconnecting with a node ID which is already connected replaces the existing
+    /// connection and is always accepted.
+    pub max_clients: Option<usize>,
+    /// Maximum number of concurrent connections from a single client key. Unlimited if
+    /// not set.
+    ///
+    /// Connections beyond this limit are rejected until one of the existing connections
+    /// of that client closes.  The registered connection of a client is not counted, since
+    /// a reconnecting client replaces it, so this only limits the connections which are
+    /// still being replaced or shut down.
+    pub max_connections_per_client: Option<usize>,
 }
 
 /// Per-client rate limit configuration.
@@ -214,6 +292,28 @@ pub struct ClientRateLimit {
     pub bytes_per_second: NonZeroU32,
     /// Max number of bytes to read in a single burst.
     pub max_burst_bytes: Option<NonZeroU32>,
+    /// Max number of frames per second to read from the client connection.
+    ///
+    /// Unlimited if not set, in which case only [`ClientRateLimit::bytes_per_second`] is
+    /// enforced.
+    pub frames_per_second: Option<NonZeroU32>,
+}
+
+/// Statistics about a currently connected client.
+///
+/// Returned by [`Server::client_stats`] to inspect which clients are using the relay.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientStats {
+    /// The node ID of the client.
+    pub node_id: NodeId,
+    /// Number of packet bytes received from this client.
+    pub bytes_recv: u64,
+    /// Number of packet bytes sent to this client.
+    pub bytes_sent: u64,
+    /// Number of packets received from this client.
+    pub packets_recv: u64,
+    /// Number of packets sent to this client.
+    pub packets_sent: u64,
 }
 
 /// TLS certificate configuration.
@@ -250,6 +350,8 @@ pub struct Server {
     https_addr: Option<SocketAddr>,
     /// The address of the QUIC server, if configured.
     quic_addr: Option<SocketAddr>,
+    /// The address of the admin HTTP service, if configured.
+    admin_addr: Option<SocketAddr>,
     /// Handle to the relay server.
     relay_handle: Option<http_server::ServerHandle>,
     /// Handle to the quic server.
@@ -348,14 +450,17 @@ impl Server {
         let quic_server = match config.quic {
             Some(quic_config) => {
                 debug!("Starting QUIC server {}", quic_config.bind_addr);
-                Some(QuicServer::spawn(quic_config).context(QuicSpawnSnafu)?)
+                Some(
+                    QuicServer::spawn(quic_config, metrics.server.clone())
+                        .context(QuicSpawnSnafu)?,
+                )
             }
             None => None,
         };
         let quic_addr = quic_server.as_ref().map(|srv| srv.bind_addr());
         let quic_handle = quic_server.as_ref().map(|srv| srv.handle());
 
-        let (relay_server, http_addr) = match config.relay {
+        let (relay_server, http_addr, admin_addr) = match config.relay {
             Some(relay_config) => {
                 debug!("Starting Relay server");
                 let mut headers = HeaderMap::new();
@@ -377,10 +482,20 @@ impl Server {
                     .request_handler(Method::GET, "/", Box::new(root_handler))
                     .request_handler(Method::GET, "/index.html", Box::new(root_handler))
                     .request_handler(Method::GET, RELAY_PROBE_PATH, Box::new(probe_handler))
+                    .request_handler(Method::GET, HEALTHZ_PATH, Box::new(healthz_handler))
                     .request_handler(Method::GET, "/robots.txt", Box::new(robots_handler));
                 if let Some(cfg) = relay_config.limits.client_rx {
                     builder = builder.client_rx_ratelimit(cfg);
                 }
+                if let Some(max_clients) = relay_config.limits.max_clients {
+                    builder = builder.max_clients(max_clients);
+                }
+                if let Some(max) = relay_config.limits.max_connections_per_client {
+                    builder = builder.max_connections_per_client(max);
+                }
+                if let Some(mesh_config) = relay_config.mesh {
+                    builder = builder.mesh(mesh::Mesh::spawn(mesh_config, metrics.server.clone()));
+                }
                 let http_addr = match relay_config.tls {
                     Some(tls_config) => {
                         let server_tls_config = match tls_config.cert {
@@ -444,9 +559,27 @@ impl Server {
                     }
                 };
                 let relay_server = builder.spawn().await?;
-                (Some(relay_server), http_addr)
+                let admin_addr = match relay_config.admin_bind_addr {
+                    Some(addr) => {
+                        let listener = TcpListener::bind(&addr)
+                            .await
+                            .map_err(|_| BindTcpListenerSnafu { addr }.build())?;
+                        let admin_addr = listener.local_addr().context(NoLocalAddrSnafu)?;
+                        let handle = relay_server.handle();
+                        tasks.spawn(
+                            async move {
+                                run_admin_service(listener, handle).await;
+                                Ok(())
+                            }
+                            .instrument(info_span!("admin-service", addr = %admin_addr)),
+                        );
+                        Some(admin_addr)
+                    }
+                    None => None,
+                };
+                (Some(relay_server), http_addr, admin_addr)
             }
-            None => (None, None),
+            None => (None, None, None),
         };
         // If http_addr is Some then relay_server is serving HTTPS.  If http_addr is None
         // relay_server is serving HTTP, including the /generate_204 service.
@@ -458,6 +591,7 @@ impl Server {
             http_addr: http_addr.or(relay_addr),
             https_addr: http_addr.and(relay_addr),
             quic_addr,
+            admin_addr,
             relay_handle,
             quic_handle,
             supervisor: AbortOnDropHandle::new(task),
@@ -504,6 +638,11 @@ impl Server {
         self.quic_addr
     }
 
+    /// The socket address the admin HTTP service is listening on.
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+
     /// The certificates chain if configured with manual TLS certificates.
     pub fn certificates(&self) -> Option<Vec<rustls::pki_types::CertificateDer<'static>>> {
         self.certificates.clone()
@@ -537,6 +676,24 @@ impl Server {
     pub fn metrics(&self) -> &RelayMetrics {
         &self.metrics
     }
+
+    /// Returns statistics about the currently connected clients.
+    ///
+    /// The clients are sorted by the total number of bytes relayed, the heaviest talkers
+    /// first.  Returns an empty list if the relay service is not enabled.
+    pub fn client_stats(&self) -> Vec<ClientStats> {
+        self.relay_handle
+            .as_ref()
+            .map(top_talkers)
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the [`ClientStats`] of the relay server, the heaviest talkers first.
+fn top_talkers(handle: &http_server::ServerHandle) -> Vec<ClientStats> {
+    let mut stats = handle.client_stats();
+    stats.sort_by_key(|s| std::cmp::Reverse(s.bytes_recv + s.bytes_sent));
+    stats
 }
 
 /// Supervisor for the relay server tasks.
@@ -625,6 +782,20 @@ fn probe_handler(
         .map_err(|err| Box::new(err) as HyperError)
 }
 
+/// Health check for load balancers and monitoring.
+///
+/// Responds with `200 OK` as long as the relay server is serving requests.
+fn healthz_handler(
+    _r: Request<Incoming>,
+    response: ResponseBuilder,
+) -> HyperResult<Response<BytesBody>> {
+    response
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body("OK".into())
+        .map_err(|err| Box::new(err) as HyperError)
+}
+
 fn robots_handler(
     _r: Request<Incoming>,
     response: ResponseBuilder,
@@ -746,9 +917,91 @@ impl hyper::service::Service<Request<Incoming>> for CaptivePortalService {
     }
 }
 
+/// Runs the admin HTTP service.
+async fn run_admin_service(listener: TcpListener, relay: http_server::ServerHandle) {
+    info!("serving");
+
+    // If this future is cancelled, this is dropped and all tasks are aborted.
+    let mut tasks = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            biased;
+
+            Some(res) = tasks.join_next() => {
+                if let Err(err) = res {
+                    if err.is_panic() {
+                        panic!("task panicked: {err:#?}");
+                    }
+                }
+            }
+
+            res = listener.accept() => {
+                match res {
+                    Ok((stream, peer_addr)) => {
+                        debug!(%peer_addr, "Connection opened",);
+                        let handler = AdminService {
+                            relay: relay.clone(),
+                        };
+
+                        tasks.spawn(async move {
+                            let stream = hyper_util::rt::TokioIo::new(stream);
+                            if let Err(err) = hyper::server::conn::http1::Builder::new()
+                                .serve_connection(stream, handler)
+                                .await
+                            {
+                                error!("Failed to serve connection: {err:?}");
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        error!("[AdminService] failed to accept connection: {:#?}", err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AdminService {
+    relay: http_server::ServerHandle,
+}
+
+impl hyper::service::Service<Request<Incoming>> for AdminService {
+    type Response = Response<BytesBody>;
+    type Error = HyperError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let r = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/clients") => match serde_json::to_vec(&top_talkers(&self.relay)) {
+                Ok(body) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(body.into()),
+                Err(err) => {
+                    error!("failed to serialize client stats: {err:#}");
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(body_empty())
+                }
+            },
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(NOTFOUND.into()),
+        };
+        let r = r.map_err(|err| Box::new(err) as HyperError);
+        Box::pin(async move { r })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{net::Ipv4Addr, time::Duration};
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        time::Duration,
+    };
 
     use bytes::Bytes;
     use http::{header::UPGRADE, StatusCode};
@@ -759,13 +1012,14 @@ mod tests {
     use tracing_test::traced_test;
 
     use super::{
-        Access, AccessConfig, RelayConfig, Server, ServerConfig, SpawnError,
+        Access, AccessConfig, MeshConfig, MeshPeer, RelayConfig, Server, ServerConfig, SpawnError,
         NO_CONTENT_CHALLENGE_HEADER, NO_CONTENT_RESPONSE_HEADER,
     };
     use crate::{
         client::{conn::ReceivedMessage, ClientBuilder, SendMessage},
         dns::DnsResolver,
         http::{Protocol, HTTP_UPGRADE_PROTOCOL},
+        protos::relay::Frame,
     };
 
     async fn spawn_local_relay() -> std::result::Result<Server, SpawnError> {
@@ -776,6 +1030,8 @@ mod tests {
                 limits: Default::default(),
                 key_cache_capacity: Some(1024),
                 access: AccessConfig::Everyone,
+                mesh: None,
+                admin_bind_addr: None,
             }),
             quic: None,
             metrics_addr: None,
@@ -832,6 +1088,8 @@ mod tests {
                 limits: Default::default(),
                 key_cache_capacity: Some(1024),
                 access: AccessConfig::Everyone,
+                mesh: None,
+                admin_bind_addr: None,
             }),
             quic: None,
             metrics_addr: Some((Ipv4Addr::LOCALHOST, 1234).into()),
@@ -857,6 +1115,86 @@ mod tests {
         assert!(body.contains("iroh.computer"));
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_healthz_handler() -> Result {
+        let server = spawn_local_relay().await?;
+        let url = format!("http://{}/healthz", server.http_addr().unwrap());
+
+        let response = reqwest::get(&url).await.context("get")?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.context("text")?, "OK");
+
+        // The connected clients gauge follows connecting clients.
+        let relay_url: RelayUrl = format!("http://{}", server.http_addr().unwrap()).parse()?;
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let client = ClientBuilder::new(relay_url, secret_key, dns_resolver())
+            .connect()
+            .await?;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.metrics().server.clients_connected.get() != 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("timeout")?;
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.metrics().server.clients_connected.get() != 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("timeout")?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_admin_service() -> Result {
+        let server = Server::spawn(ServerConfig::<(), ()> {
+            relay: Some(RelayConfig::<(), ()> {
+                http_bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+                tls: None,
+                limits: Default::default(),
+                key_cache_capacity: Some(1024),
+                access: AccessConfig::Everyone,
+                mesh: None,
+                admin_bind_addr: Some((Ipv4Addr::LOCALHOST, 0).into()),
+            }),
+            quic: None,
+            metrics_addr: None,
+        })
+        .await?;
+        let relay_url: RelayUrl = format!("http://{}", server.http_addr().unwrap()).parse()?;
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+        let _client = ClientBuilder::new(relay_url, secret_key, dns_resolver())
+            .connect()
+            .await?;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.client_stats().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("timeout")?;
+
+        let url = format!("http://{}/clients", server.admin_addr().unwrap());
+        let response = reqwest::get(&url).await.context("get")?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await.context("text")?;
+        let clients: Vec<serde_json::Value> = serde_json::from_str(&body).context("json")?;
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0]["node_id"], node_id.to_string());
+
+        let url = format!("http://{}/nope", server.admin_addr().unwrap());
+        let response = reqwest::get(&url).await.context("get")?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_captive_portal_service() {
@@ -1066,6 +1404,30 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_access_config_lists() {
+        let a = SecretKey::generate(rand::thread_rng()).public();
+        let b = SecretKey::generate(rand::thread_rng()).public();
+
+        let allow = AccessConfig::allowlist([a]);
+        assert!(allow.is_allowed(a).await);
+        assert!(!allow.is_allowed(b).await);
+
+        let deny = AccessConfig::denylist([a]);
+        assert!(!deny.is_allowed(a).await);
+        assert!(deny.is_allowed(b).await);
+
+        let callback = AccessConfig::restricted(move |node_id| async move {
+            if node_id == b {
+                Access::Allow
+            } else {
+                Access::Deny
+            }
+        });
+        assert!(!callback.is_allowed(a).await);
+        assert!(callback.is_allowed(b).await);
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_relay_access_control() -> Result<()> {
@@ -1093,6 +1455,8 @@ mod tests {
                     }
                     .boxed()
                 })),
+                mesh: None,
+                admin_bind_addr: None,
             }),
             quic: None,
             metrics_addr: None,
@@ -1160,6 +1524,147 @@ mod tests {
         Ok(())
     }
 
+    async fn spawn_mesh_relay(
+        http_bind_addr: SocketAddr,
+        secret_key: SecretKey,
+        peers: Vec<MeshPeer>,
+    ) -> std::result::Result<Server, SpawnError> {
+        Server::spawn(ServerConfig::<(), ()> {
+            relay: Some(RelayConfig::<(), ()> {
+                http_bind_addr,
+                tls: None,
+                limits: Default::default(),
+                key_cache_capacity: Some(1024),
+                access: AccessConfig::Everyone,
+                mesh: Some(MeshConfig { secret_key, peers }),
+                admin_bind_addr: None,
+            }),
+            quic: None,
+            metrics_addr: None,
+        })
+        .await
+    }
+
+    /// Polls `f` until it returns true, failing after 5 seconds.
+    async fn wait_for(mut f: impl FnMut() -> bool) -> Result<()> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !f() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("timeout")
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_relay_mesh_forwarding() -> Result<()> {
+        // The relays need to know each other's addresses before they are spawned.
+        let listeners = (0..3)
+            .map(|_| std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)))
+            .collect::<std::io::Result<Vec<_>>>()
+            .context("bind")?;
+        let addrs = listeners
+            .iter()
+            .map(|l| l.local_addr())
+            .collect::<std::io::Result<Vec<_>>>()
+            .context("local addr")?;
+        drop(listeners);
+        let mesh_keys: Vec<_> = (0..3)
+            .map(|_| SecretKey::generate(rand::thread_rng()))
+            .collect();
+        let mut relay_urls = Vec::new();
+        for addr in &addrs {
+            relay_urls.push(format!("http://{addr}").parse::<RelayUrl>()?);
+        }
+        let mut servers = Vec::new();
+        for i in 0..3 {
+            let peers = (0..3)
+                .filter(|j| *j != i)
+                .map(|j| MeshPeer {
+                    url: relay_urls[j].clone(),
+                    node_id: mesh_keys[j].public(),
+                })
+                .collect();
+            servers.push(spawn_mesh_relay(addrs[i], mesh_keys[i].clone(), peers).await?);
+        }
+        let metrics: Vec<_> = servers.iter().map(|s| s.metrics().server.clone()).collect();
+
+        // Client x is homed on relay 1, client y on relay 0.
+        let resolver = dns_resolver();
+        let x_secret_key = SecretKey::generate(rand::thread_rng());
+        let x_key = x_secret_key.public();
+        let mut client_x =
+            ClientBuilder::new(relay_urls[1].clone(), x_secret_key, resolver.clone())
+                .connect()
+                .await?;
+        let y_secret_key = SecretKey::generate(rand::thread_rng());
+        let y_key = y_secret_key.public();
+        let mut client_y =
+            ClientBuilder::new(relay_urls[0].clone(), y_secret_key, resolver.clone())
+                .connect()
+                .await?;
+
+        // y sends to x through relay 0, which forwards to relay 1 only.
+        let msg = Bytes::from("hello, x");
+        let res = try_send_recv(&mut client_y, &mut client_x, x_key, msg.clone()).await?;
+        let ReceivedMessage::ReceivedPacket {
+            remote_node_id,
+            data,
+        } = res
+        else {
+            panic!("client_x received unexpected message {res:?}");
+        };
+        assert_eq!(remote_node_id, y_key);
+        assert_eq!(data, msg);
+        assert!(metrics[0].mesh_packets_forwarded.get() > 0);
+        assert_eq!(
+            metrics[0].mesh_packets_forwarded.get(),
+            metrics[1].mesh_packets_recv.get()
+        );
+        assert_eq!(metrics[2].mesh_packets_recv.get(), 0);
+
+        // Packets for nodes not connected to any relay are dropped.
+        let dropped = metrics[0].send_packets_dropped.get();
+        let unknown = SecretKey::generate(rand::thread_rng()).public();
+        client_y
+            .send(SendMessage::SendPacket(unknown, msg.clone()))
+            .await?;
+        wait_for(|| metrics[0].send_packets_dropped.get() > dropped).await?;
+
+        // Clients which are not mesh peers can not inject forwarded packets.
+        let z_secret_key = SecretKey::generate(rand::thread_rng());
+        let client_z = ClientBuilder::new(relay_urls[1].clone(), z_secret_key, resolver)
+            .connect()
+            .await?;
+        let mut conn = client_z.into_conn();
+        conn.send(Frame::ForwardPacket {
+            src_key: y_key,
+            dst_key: x_key,
+            packet: msg.clone(),
+        })
+        .await?;
+        let res = tokio::time::timeout(Duration::from_millis(500), client_x.next()).await;
+        assert!(res.is_err(), "unexpected message {res:?}");
+
+        // Once x disconnects, relay 0 stops forwarding packets for it.
+        drop(client_x);
+        let dropped = metrics[0].send_packets_dropped.get();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while metrics[0].send_packets_dropped.get() == dropped {
+                client_y
+                    .send(SendMessage::SendPacket(x_key, msg.clone()))
+                    .await?;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Ok::<_, crate::client::SendError>(())
+        })
+        .await
+        .context("timeout")??;
+
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_relay_clients_full() -> Result<()> {
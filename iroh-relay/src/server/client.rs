@@ -1,7 +1,15 @@
 //! The server-side representation of an ongoing client relaying connection.
 
 use std::{
-    collections::HashSet, future::Future, num::NonZeroU32, pin::Pin, sync::Arc, task::Poll,
+    collections::HashSet,
+    future::Future,
+    num::NonZeroU32,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::Poll,
     time::Duration,
 };
 
@@ -25,10 +33,10 @@ use crate::{
         relay::{write_frame, Frame, SendError as SendRelayError, PING_INTERVAL},
     },
     server::{
-        clients::Clients,
+        clients::{Clients, ConnectionGuard},
         metrics::Metrics,
         streams::{RelayedStream, StreamError},
-        ClientRateLimit,
+        ClientRateLimit, ClientStats,
     },
     PingTracker,
 };
@@ -50,6 +58,8 @@ pub(super) struct Config {
     pub(super) write_timeout: Duration,
     pub(super) channel_capacity: usize,
     pub(super) rate_limit: Option<ClientRateLimit>,
+    /// Tracks this connection in the per-node connection count, if limited.
+    pub(super) connection: Option<ConnectionGuard>,
 }
 
 /// The [`Server`] side representation of a [`Client`]'s connection.
@@ -72,6 +82,19 @@ pub(super) struct Client {
     disco_send_queue: mpsc::Sender<Packet>,
     /// Channel to notify the client that a previous sender has disconnected.
     peer_gone: mpsc::Sender<NodeId>,
+    /// Traffic counters for this connection.
+    counters: Arc<Counters>,
+    /// Keeps this connection counted until the client is dropped.
+    _connection: Option<ConnectionGuard>,
+}
+
+/// Per-connection traffic counters, shared between the [`Client`] and its [`Actor`].
+#[derive(Debug, Default)]
+struct Counters {
+    bytes_recv: AtomicU64,
+    bytes_sent: AtomicU64,
+    packets_recv: AtomicU64,
+    packets_sent: AtomicU64,
 }
 
 impl Client {
@@ -90,6 +113,7 @@ impl Client {
             write_timeout,
             channel_capacity,
             rate_limit,
+            connection,
         } = config;
 
         let stream = match rate_limit {
@@ -99,7 +123,10 @@ impl Client {
                     quota = quota.allow_burst(max_burst);
                 }
                 let limiter = governor::RateLimiter::direct(quota);
-                RateLimitedRelayedStream::new(io, limiter, metrics.clone())
+                let frame_limiter = cfg
+                    .frames_per_second
+                    .map(|fps| governor::RateLimiter::direct(governor::Quota::per_second(fps)));
+                RateLimitedRelayedStream::new(io, limiter, frame_limiter, metrics.clone())
             }
             None => RateLimitedRelayedStream::unlimited(io, metrics.clone()),
         };
@@ -109,6 +136,7 @@ impl Client {
 
         let (disco_send_queue_s, disco_send_queue_r) = mpsc::channel(channel_capacity);
         let (peer_gone_s, peer_gone_r) = mpsc::channel(channel_capacity);
+        let counters = Arc::new(Counters::default());
 
         let actor = Actor {
             stream,
@@ -122,6 +150,7 @@ impl Client {
             client_counter: ClientCounter::default(),
             ping_tracker: PingTracker::default(),
             metrics,
+            counters: counters.clone(),
         };
 
         // start io loop
@@ -140,6 +169,8 @@ impl Client {
             send_queue: send_queue_s,
             disco_send_queue: disco_send_queue_s,
             peer_gone: peer_gone_s,
+            counters,
+            _connection: connection,
         }
     }
 
@@ -147,6 +178,17 @@ impl Client {
         self.connection_id
     }
 
+    /// Returns a snapshot of the traffic statistics of this connection.
+    pub(super) fn stats(&self) -> ClientStats {
+        ClientStats {
+            node_id: self.node_id,
+            bytes_recv: self.counters.bytes_recv.load(Ordering::Relaxed),
+            bytes_sent: self.counters.bytes_sent.load(Ordering::Relaxed),
+            packets_recv: self.counters.packets_recv.load(Ordering::Relaxed),
+            packets_sent: self.counters.packets_sent.load(Ordering::Relaxed),
+        }
+    }
+
     /// Shutdown the reader and writer loops and closes the connection.
     ///
     /// Any shutdown errors will be logged as warnings.
@@ -315,6 +357,8 @@ struct Actor {
     client_counter: ClientCounter,
     ping_tracker: PingTracker,
     metrics: Arc<Metrics>,
+    /// Traffic counters for this connection.
+    counters: Arc<Counters>,
 }
 
 impl Actor {
@@ -323,6 +367,7 @@ impl Actor {
         // connection is accepted long before this in the HTTP server, but it is clearer to
         // handle the metric here.
         self.metrics.accepts.inc();
+        self.metrics.clients_connected.inc();
         if self.client_counter.update(self.node_id) {
             self.metrics.unique_client_keys.inc();
         }
@@ -337,6 +382,7 @@ impl Actor {
 
         self.clients.unregister(self.connection_id, self.node_id);
         self.metrics.disconnects.inc();
+        self.metrics.clients_connected.dec();
     }
 
     async fn run_inner(&mut self, done: CancellationToken) -> Result<(), RunError> {
@@ -422,7 +468,9 @@ impl Actor {
 
         if let Ok(len) = content.len().try_into() {
             self.metrics.bytes_sent.inc_by(len);
+            self.counters.bytes_sent.fetch_add(len, Ordering::Relaxed);
         }
+        self.counters.packets_sent.fetch_add(1, Ordering::Relaxed);
         self.write_frame(Frame::RecvPacket { src_key, content })
             .await
     }
@@ -475,6 +523,36 @@ impl Actor {
                     warn!("failed to handle send packet frame: {err:#}");
                 }
                 self.metrics.bytes_recv.inc_by(packet_len as u64);
+                self.counters
+                    .bytes_recv
+                    .fetch_add(packet_len as u64, Ordering::Relaxed);
+                self.counters.packets_recv.fetch_add(1, Ordering::Relaxed);
+            }
+            Frame::ForwardPacket {
+                src_key,
+                dst_key,
+                packet,
+            } => {
+                if !self.clients.is_mesh_peer(&self.node_id) {
+                    warn!("dropping forwarded packet from client which is not a mesh peer");
+                    self.metrics.unknown_frames.inc();
+                    return Ok(());
+                }
+                self.metrics.mesh_packets_recv.inc();
+                if let Err(err @ ForwardPacketError { .. }) =
+                    self.handle_frame_forward_packet(src_key, dst_key, packet)
+                {
+                    warn!("failed to handle forward packet frame: {err:#}");
+                }
+            }
+            Frame::MeshPresence { node_id, present } => {
+                if !self.clients.is_mesh_peer(&self.node_id) {
+                    warn!("dropping mesh presence from client which is not a mesh peer");
+                    self.metrics.unknown_frames.inc();
+                    return Ok(());
+                }
+                self.clients
+                    .update_mesh_route(self.node_id, node_id, present);
             }
             Frame::Ping { data } => {
                 self.metrics.got_ping.inc();
@@ -505,6 +583,21 @@ impl Actor {
         }
         Ok(())
     }
+
+    fn handle_frame_forward_packet(
+        &self,
+        src: NodeId,
+        dst: NodeId,
+        data: Bytes,
+    ) -> Result<(), ForwardPacketError> {
+        let scope = if disco::looks_like_disco_wrapper(&data) {
+            PacketScope::Disco
+        } else {
+            PacketScope::Data
+        };
+        self.clients
+            .send_forwarded_packet(scope, dst, data, src, &self.metrics)
+    }
 }
 
 #[derive(Debug)]
@@ -547,6 +640,8 @@ impl ForwardPacketError {
 struct RateLimitedRelayedStream {
     inner: RelayedStream,
     limiter: Option<Arc<governor::DefaultDirectRateLimiter>>,
+    /// Optional limiter on the number of frames, in addition to the bytes `limiter`.
+    frame_limiter: Option<Arc<governor::DefaultDirectRateLimiter>>,
     state: State,
     /// Keeps track if this stream was ever rate-limited.
     limited_once: bool,
@@ -569,11 +664,13 @@ impl RateLimitedRelayedStream {
     fn new(
         inner: RelayedStream,
         limiter: governor::DefaultDirectRateLimiter,
+        frame_limiter: Option<governor::DefaultDirectRateLimiter>,
         metrics: Arc<Metrics>,
     ) -> Self {
         Self {
             inner,
             limiter: Some(Arc::new(limiter)),
+            frame_limiter: frame_limiter.map(Arc::new),
             state: State::Ready,
             limited_once: false,
             metrics,
@@ -584,6 +681,7 @@ impl RateLimitedRelayedStream {
         Self {
             inner,
             limiter: None,
+            frame_limiter: None,
             state: State::Ready,
             limited_once: false,
             metrics,
@@ -616,6 +714,7 @@ impl Stream for RateLimitedRelayedStream {
             return Pin::new(&mut self.inner).poll_next(cx);
         };
         let limiter = limiter.clone();
+        let frame_limiter = self.frame_limiter.clone();
         loop {
             match &mut self.state {
                 State::Ready => {
@@ -635,14 +734,35 @@ impl Stream for RateLimitedRelayedStream {
                                     };
 
                                     match limiter.check_n(frame_len) {
-                                        Ok(Ok(_)) => return Poll::Ready(Some(item)),
+                                        Ok(Ok(_)) => {
+                                            let Some(ref frame_limiter) = frame_limiter else {
+                                                return Poll::Ready(Some(item));
+                                            };
+                                            if frame_limiter.check().is_ok() {
+                                                return Poll::Ready(Some(item));
+                                            }
+                                            // Item is rate-limited by the frame rate.
+                                            self.record_rate_limited();
+                                            let delay = Box::pin({
+                                                let frame_limiter = frame_limiter.clone();
+                                                async move {
+                                                    frame_limiter.until_ready().await;
+                                                }
+                                            });
+                                            self.state = State::Blocked { delay, item };
+                                            continue;
+                                        }
                                         Ok(Err(_)) => {
                                             // Item is rate-limited.
                                             self.record_rate_limited();
                                             let delay = Box::pin({
                                                 let limiter = limiter.clone();
+                                                let frame_limiter = frame_limiter.clone();
                                                 async move {
                                                     limiter.until_n_ready(frame_len).await.ok();
+                                                    if let Some(frame_limiter) = frame_limiter {
+                                                        frame_limiter.until_ready().await;
+                                                    }
                                                 }
                                             });
                                             self.state = State::Blocked { delay, item };
@@ -792,6 +912,7 @@ mod tests {
             client_counter: ClientCounter::default(),
             ping_tracker: PingTracker::default(),
             metrics,
+            counters: Default::default(),
         };
 
         let done = CancellationToken::new();
@@ -900,7 +1021,7 @@ mod tests {
             MaybeTlsStream::Test(io_read),
             RelayCodec::test(),
         ));
-        let mut stream = RateLimitedRelayedStream::new(stream, limiter, Default::default());
+        let mut stream = RateLimitedRelayedStream::new(stream, limiter, None, Default::default());
 
         // Prepare a frame to send, assert its size.
         let data = Bytes::from_static(b"hello world!!");
@@ -945,4 +1066,57 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_frame_rate_limit() -> Result {
+        // Generous byte limit, but only a single frame per second.
+        let quota = governor::Quota::per_second(NonZeroU32::try_from(10_000).unwrap());
+        let limiter = governor::RateLimiter::direct(quota);
+        let frame_quota = governor::Quota::per_second(NonZeroU32::try_from(1).unwrap());
+        let frame_limiter = governor::RateLimiter::direct(frame_quota);
+
+        let (io_read, io_write) = tokio::io::duplex(1024);
+        let mut frame_writer = Framed::new(io_write, RelayCodec::test());
+        let stream = RelayedStream::Relay(Framed::new(
+            MaybeTlsStream::Test(io_read),
+            RelayCodec::test(),
+        ));
+        let metrics = Arc::new(Metrics::default());
+        let mut stream =
+            RateLimitedRelayedStream::new(stream, limiter, Some(frame_limiter), metrics.clone());
+
+        let target = SecretKey::generate(rand::thread_rng()).public();
+        let frame = Frame::SendPacket {
+            dst_key: target,
+            packet: Bytes::from_static(b"hello world!!"),
+        };
+
+        // First frame arrives.
+        frame_writer.send(frame.clone()).await.context("send")?;
+        frame_writer.flush().await.context("flush")?;
+        let recv_frame = tokio::time::timeout(Duration::from_millis(500), stream.next())
+            .await
+            .expect("timeout")
+            .expect("option")
+            .expect("ok");
+        assert_eq!(recv_frame, frame);
+
+        // Second frame is held back by the frame limiter.
+        frame_writer.send(frame.clone()).await.context("send")?;
+        frame_writer.flush().await.context("flush")?;
+        let res = tokio::time::timeout(Duration::from_millis(100), stream.next()).await;
+        assert!(res.is_err(), "expecting a timeout");
+        assert_eq!(metrics.frames_rx_ratelimited_total.get(), 1);
+
+        // Once the quota refills the frame arrives.
+        let recv_frame = tokio::time::timeout(Duration::from_millis(1500), stream.next())
+            .await
+            .expect("timeout")
+            .expect("option")
+            .expect("ok");
+        assert_eq!(recv_frame, frame);
+
+        Ok(())
+    }
 }
@@ -5,7 +5,7 @@ use std::{
     collections::HashSet,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, Mutex,
     },
 };
 
@@ -15,10 +15,14 @@ use iroh_base::NodeId;
 use tokio::sync::mpsc::error::TrySendError;
 use tracing::{debug, trace};
 
-use super::client::{Client, Config, ForwardPacketError};
+use super::{
+    client::{Client, Config, ForwardPacketError},
+    mesh::Mesh,
+};
 use crate::server::{
     client::{PacketScope, SendError},
     metrics::Metrics,
+    ClientStats,
 };
 
 /// Manages the connections to all currently connected clients.
@@ -33,9 +37,23 @@ struct Inner {
     sent_to: DashMap<NodeId, HashSet<NodeId>>,
     /// Connection ID Counter
     next_connection_id: AtomicU64,
+    /// Serializes the capacity check with the insertion of a new client.
+    register_lock: Mutex<()>,
+    /// Number of open connections per node, including those not yet registered.
+    connections: DashMap<NodeId, usize>,
+    /// The mesh peers to forward packets for unknown nodes to.
+    mesh: Option<Mesh>,
 }
 
 impl Clients {
+    /// Creates the client registry, forwarding packets for unknown nodes to the `mesh`.
+    pub(super) fn new(mesh: Option<Mesh>) -> Self {
+        Self(Arc::new(Inner {
+            mesh,
+            ..Default::default()
+        }))
+    }
+
     pub async fn shutdown(&self) {
         let keys: Vec<_> = self.0.clients.iter().map(|x| *x.key()).collect();
         trace!("shutting down {} clients", keys.len());
@@ -46,19 +64,124 @@ impl Clients {
     }
 
     /// Builds the client handler and starts the read & write loops for the connection.
-    pub async fn register(&self, client_config: Config, metrics: Arc<Metrics>) {
+    ///
+    /// If `max_clients` is set and that many other clients are already connected, the
+    /// client is not registered and the config is returned.  A client reconnecting with a
+    /// node ID which is already connected replaces the existing connection and is always
+    /// registered.
+    pub async fn register(
+        &self,
+        client_config: Config,
+        max_clients: Option<usize>,
+        metrics: Arc<Metrics>,
+    ) -> Result<(), Box<Config>> {
         let node_id = client_config.node_id;
-        let connection_id = self.get_connection_id();
-        trace!(remote_node = node_id.fmt_short(), "registering client");
-
-        let client = Client::new(client_config, connection_id, self, metrics);
-        if let Some(old_client) = self.0.clients.insert(node_id, client) {
+        let old_client = {
+            // Checking the capacity and inserting must be atomic, otherwise concurrent
+            // registrations could exceed `max_clients`.
+            let _guard = self.0.register_lock.lock().expect("poisoned");
+            if let Some(max_clients) = max_clients {
+                if self.0.clients.len() >= max_clients && !self.0.clients.contains_key(&node_id) {
+                    return Err(Box::new(client_config));
+                }
+            }
+            if let Some(ref mesh) = self.0.mesh {
+                if mesh.is_peer(&node_id) {
+                    // A reconnecting mesh peer announces all its clients again, the routes
+                    // announced over its previous connection are stale.  Cleared before the
+                    // new client starts reading the announcements.
+                    mesh.remove_peer(&node_id);
+                }
+            }
+            let connection_id = self.get_connection_id();
+            trace!(remote_node = node_id.fmt_short(), "registering client");
+            let client = Client::new(client_config, connection_id, self, metrics);
+            let old_client = self.0.clients.insert(node_id, client);
+            if let Some(ref mesh) = self.0.mesh {
+                if !mesh.is_peer(&node_id) {
+                    mesh.client_connected(node_id);
+                }
+            }
+            old_client
+        };
+        if let Some(old_client) = old_client {
             debug!(
                 remote_node = node_id.fmt_short(),
                 "multiple connections found, pruning old connection",
             );
             old_client.shutdown().await;
         }
+        Ok(())
+    }
+
+    /// Tracks a new connection from `node_id`.
+    ///
+    /// Returns `None` if there are already `max_connections` connections from this node.
+    /// The registered connection of the node is not counted, since registering the new
+    /// connection replaces it.  The connection is tracked until the returned guard is
+    /// dropped.
+    pub(super) fn add_connection(
+        &self,
+        node_id: NodeId,
+        max_connections: Option<usize>,
+    ) -> Option<ConnectionGuard> {
+        let _guard = self.0.register_lock.lock().expect("poisoned");
+        if let Some(max_connections) = max_connections {
+            let open = self.0.connections.get(&node_id).map_or(0, |count| *count);
+            let replaced = usize::from(self.0.clients.contains_key(&node_id));
+            if open.saturating_sub(replaced) >= max_connections {
+                return None;
+            }
+        }
+        *self.0.connections.entry(node_id).or_insert(0) += 1;
+        Some(ConnectionGuard {
+            clients: self.clone(),
+            node_id,
+        })
+    }
+
+    /// Returns the number of open connections from `node_id`.
+    #[cfg(test)]
+    pub(super) fn connections(&self, node_id: &NodeId) -> usize {
+        self.0.connections.get(node_id).map_or(0, |count| *count)
+    }
+
+    /// Returns the number of currently connected clients.
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.0.clients.len()
+    }
+
+    /// Returns whether the node is one of the configured mesh peers.
+    pub(super) fn is_mesh_peer(&self, node_id: &NodeId) -> bool {
+        self.0
+            .mesh
+            .as_ref()
+            .is_some_and(|mesh| mesh.is_peer(node_id))
+    }
+
+    /// Records that the mesh peer `peer` announced whether `node_id` is connected to it.
+    pub(super) fn update_mesh_route(&self, peer: NodeId, node_id: NodeId, present: bool) {
+        if let Some(ref mesh) = self.0.mesh {
+            mesh.update_route(peer, node_id, present);
+        }
+    }
+
+    /// Forwards a packet to the mesh peer `dst` is connected to.
+    ///
+    /// Returns `false` if `dst` is not connected to any mesh peer.
+    fn forward_to_mesh(&self, src: NodeId, dst: NodeId, data: Bytes) -> bool {
+        // Packets are read from the sender through its rate limiter before reaching here,
+        // so forwarding does not bypass the sender's rate limit.
+        self.0
+            .mesh
+            .as_ref()
+            .is_some_and(|mesh| mesh.forward(src, dst, data))
+    }
+
+    /// Returns the traffic statistics of all currently connected clients.
+    pub(super) fn stats(&self) -> Vec<ClientStats> {
+        self.0.clients.iter().map(|client| client.stats()).collect()
     }
 
     fn get_connection_id(&self) -> u64 {
@@ -77,11 +200,24 @@ impl Clients {
             "unregistering client"
         );
 
-        if let Some((_, client)) = self
-            .0
-            .clients
-            .remove_if(&node_id, |_, c| c.connection_id() == connection_id)
-        {
+        let removed = {
+            // Serialized with `register`, so the mesh peers see the announcements in the
+            // same order as the clients are inserted and removed.
+            let _guard = self.0.register_lock.lock().expect("poisoned");
+            let removed = self
+                .0
+                .clients
+                .remove_if(&node_id, |_, c| c.connection_id() == connection_id);
+            if let (Some(_), Some(mesh)) = (&removed, &self.0.mesh) {
+                if mesh.is_peer(&node_id) {
+                    mesh.remove_peer(&node_id);
+                } else {
+                    mesh.client_disconnected(node_id);
+                }
+            }
+            removed
+        };
+        if let Some((_, client)) = removed {
             if let Some((_, sent_to)) = self.0.sent_to.remove(&node_id) {
                 for key in sent_to {
                     match client.try_send_peer_gone(key) {
@@ -105,6 +241,9 @@ impl Clients {
     }
 
     /// Attempt to send a packet to client with [`NodeId`] `dst`.
+    ///
+    /// If `dst` is not connected to this relay server the packet is forwarded to the mesh
+    /// peer `dst` is connected to.
     pub(super) fn send_packet(
         &self,
         dst: NodeId,
@@ -113,38 +252,21 @@ impl Clients {
         metrics: &Metrics,
     ) -> Result<(), ForwardPacketError> {
         let Some(client) = self.0.clients.get(&dst) else {
+            if self.forward_to_mesh(src, dst, data) {
+                trace!(dst = dst.fmt_short(), "forwarded packet to mesh peer");
+                return Ok(());
+            }
             debug!(dst = dst.fmt_short(), "no connected client, dropped packet");
             metrics.send_packets_dropped.inc();
             return Ok(());
         };
-        match client.try_send_packet(src, data) {
-            Ok(_) => {
-                // Record sent_to relationship
-                self.0.sent_to.entry(src).or_default().insert(dst);
-                Ok(())
-            }
-            Err(TrySendError::Full(_)) => {
-                debug!(
-                    dst = dst.fmt_short(),
-                    "client too busy to receive packet, dropping packet"
-                );
-                Err(ForwardPacketError::new(PacketScope::Data, SendError::Full))
-            }
-            Err(TrySendError::Closed(_)) => {
-                debug!(
-                    dst = dst.fmt_short(),
-                    "can no longer write to client, dropping message and pruning connection"
-                );
-                client.start_shutdown();
-                Err(ForwardPacketError::new(
-                    PacketScope::Data,
-                    SendError::Closed,
-                ))
-            }
-        }
+        self.send_to_client(&client, PacketScope::Data, dst, data, src)
     }
 
     /// Attempt to send a disco packet to client with [`NodeId`] `dst`.
+    ///
+    /// If `dst` is not connected to this relay server the packet is forwarded to the mesh
+    /// peer `dst` is connected to.
     pub(super) fn send_disco_packet(
         &self,
         dst: NodeId,
@@ -153,6 +275,10 @@ impl Clients {
         metrics: &Metrics,
     ) -> Result<(), ForwardPacketError> {
         let Some(client) = self.0.clients.get(&dst) else {
+            if self.forward_to_mesh(src, dst, data) {
+                trace!(dst = dst.fmt_short(), "forwarded disco packet to mesh peer");
+                return Ok(());
+            }
             debug!(
                 dst = dst.fmt_short(),
                 "no connected client, dropped disco packet"
@@ -160,7 +286,45 @@ impl Clients {
             metrics.disco_packets_dropped.inc();
             return Ok(());
         };
-        match client.try_send_disco_packet(src, data) {
+        self.send_to_client(&client, PacketScope::Disco, dst, data, src)
+    }
+
+    /// Delivers a packet forwarded by a mesh peer to the client with [`NodeId`] `dst`.
+    ///
+    /// Forwarded packets are never forwarded again: if `dst` is not connected to this
+    /// relay server the packet is dropped.
+    pub(super) fn send_forwarded_packet(
+        &self,
+        scope: PacketScope,
+        dst: NodeId,
+        data: Bytes,
+        src: NodeId,
+        metrics: &Metrics,
+    ) -> Result<(), ForwardPacketError> {
+        let Some(client) = self.0.clients.get(&dst) else {
+            trace!(
+                dst = dst.fmt_short(),
+                "no connected client, dropped forwarded packet"
+            );
+            metrics.mesh_packets_dropped.inc();
+            return Ok(());
+        };
+        self.send_to_client(&client, scope, dst, data, src)
+    }
+
+    fn send_to_client(
+        &self,
+        client: &Client,
+        scope: PacketScope,
+        dst: NodeId,
+        data: Bytes,
+        src: NodeId,
+    ) -> Result<(), ForwardPacketError> {
+        let res = match scope {
+            PacketScope::Data => client.try_send_packet(src, data),
+            PacketScope::Disco => client.try_send_disco_packet(src, data),
+        };
+        match res {
             Ok(_) => {
                 // Record sent_to relationship
                 self.0.sent_to.entry(src).or_default().insert(dst);
@@ -169,25 +333,43 @@ impl Clients {
             Err(TrySendError::Full(_)) => {
                 debug!(
                     dst = dst.fmt_short(),
-                    "client too busy to receive disco packet, dropping packet"
+                    ?scope,
+                    "client too busy to receive packet, dropping packet"
                 );
-                Err(ForwardPacketError::new(PacketScope::Disco, SendError::Full))
+                Err(ForwardPacketError::new(scope, SendError::Full))
             }
             Err(TrySendError::Closed(_)) => {
                 debug!(
                     dst = dst.fmt_short(),
-                    "can no longer write to client, dropping disco message and pruning connection"
+                    ?scope,
+                    "can no longer write to client, dropping message and pruning connection"
                 );
                 client.start_shutdown();
-                Err(ForwardPacketError::new(
-                    PacketScope::Disco,
-                    SendError::Closed,
-                ))
+                Err(ForwardPacketError::new(scope, SendError::Closed))
             }
         }
     }
 }
 
+/// Tracks an open connection from a node, see [`Clients::add_connection`].
+#[derive(Debug)]
+pub(super) struct ConnectionGuard {
+    clients: Clients,
+    node_id: NodeId,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.clients
+            .0
+            .connections
+            .remove_if_mut(&self.node_id, |_, count| {
+                *count -= 1;
+                *count == 0
+            });
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
@@ -201,7 +383,10 @@ mod tests {
     use super::*;
     use crate::{
         protos::relay::{recv_frame, Frame, FrameType, RelayCodec},
-        server::streams::{MaybeTlsStream, RelayedStream},
+        server::{
+            streams::{MaybeTlsStream, RelayedStream},
+            MeshConfig, MeshPeer,
+        },
     };
 
     fn test_client_builder(key: NodeId) -> (Config, FramedRead<DuplexStream, RelayCodec>) {
@@ -216,6 +401,7 @@ mod tests {
                 write_timeout: Duration::from_secs(1),
                 channel_capacity: 10,
                 rate_limit: None,
+                connection: None,
             },
             FramedRead::new(test_io, RelayCodec::test()),
         )
@@ -230,7 +416,11 @@ mod tests {
 
         let clients = Clients::default();
         let metrics = Arc::new(Metrics::default());
-        clients.register(builder_a, metrics.clone()).await;
+        clients
+            .register(builder_a, None, metrics.clone())
+            .await
+            .ok()
+            .context("at capacity")?;
 
         // send packet
         let data = b"hello world!";
@@ -277,4 +467,75 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_mesh_peer_reconnect_clears_routes() -> Result {
+        let peer_key = SecretKey::generate(rand::thread_rng()).public();
+        let node_key = SecretKey::generate(rand::thread_rng()).public();
+        let src_key = SecretKey::generate(rand::thread_rng()).public();
+        let metrics = Arc::new(Metrics::default());
+        let mesh = Mesh::spawn(
+            MeshConfig {
+                secret_key: SecretKey::generate(rand::thread_rng()),
+                peers: vec![MeshPeer {
+                    url: "http://127.0.0.1:1".parse()?,
+                    node_id: peer_key,
+                }],
+            },
+            metrics.clone(),
+        );
+        let clients = Clients::new(Some(mesh.clone()));
+
+        let (builder, _peer_rw) = test_client_builder(peer_key);
+        clients
+            .register(builder, None, metrics.clone())
+            .await
+            .ok()
+            .context("at capacity")?;
+        clients.update_mesh_route(peer_key, node_key, true);
+        assert!(mesh.forward(src_key, node_key, Bytes::from_static(b"hello")));
+
+        // The peer reconnects, its new connection did not announce the node yet.
+        let (builder, _peer_rw) = test_client_builder(peer_key);
+        clients
+            .register(builder, None, metrics.clone())
+            .await
+            .ok()
+            .context("at capacity")?;
+        assert!(!mesh.forward(src_key, node_key, Bytes::from_static(b"hello")));
+
+        clients.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_connection() -> Result {
+        let a_key = SecretKey::generate(rand::thread_rng()).public();
+        let clients = Clients::default();
+        let metrics = Arc::new(Metrics::default());
+
+        // A limit of zero rejects without leaving an entry behind.
+        assert!(clients.add_connection(a_key, Some(0)).is_none());
+        assert!(!clients.0.connections.contains_key(&a_key));
+
+        let guard = clients.add_connection(a_key, Some(1)).context("rejected")?;
+        assert!(clients.add_connection(a_key, Some(1)).is_none());
+
+        // Once registered, the connection would be replaced by the next one.
+        let (mut builder_a, _a_rw) = test_client_builder(a_key);
+        builder_a.connection = Some(guard);
+        clients
+            .register(builder_a, None, metrics)
+            .await
+            .ok()
+            .context("at capacity")?;
+        let second = clients.add_connection(a_key, Some(1)).context("rejected")?;
+        assert_eq!(clients.connections(&a_key), 2);
+
+        drop(second);
+        clients.shutdown().await;
+        assert!(!clients.0.connections.contains_key(&a_key));
+
+        Ok(())
+    }
 }
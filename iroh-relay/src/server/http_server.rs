@@ -21,7 +21,9 @@ use tokio_rustls_acme::AcmeAcceptor;
 use tokio_util::{codec::Framed, sync::CancellationToken, task::AbortOnDropHandle};
 use tracing::{debug, debug_span, error, info, info_span, trace, warn, Instrument};
 
-use super::{clients::Clients, streams::StreamError, AccessConfig, SpawnError};
+use super::{
+    clients::Clients, mesh::Mesh, streams::StreamError, AccessConfig, ClientStats, SpawnError,
+};
 use crate::{
     defaults::{timeouts::SERVER_WRITE_TIMEOUT, DEFAULT_KEY_CACHE_CAPACITY},
     http::{Protocol, LEGACY_RELAY_PATH, RELAY_PATH, SUPPORTED_WEBSOCKET_VERSION},
@@ -91,6 +93,7 @@ pub(super) struct Server {
     addr: SocketAddr,
     http_server_task: AbortOnDropHandle<()>,
     cancel_server_loop: CancellationToken,
+    clients: Clients,
 }
 
 impl Server {
@@ -101,6 +104,7 @@ impl Server {
     pub(super) fn handle(&self) -> ServerHandle {
         ServerHandle {
             cancel_token: self.cancel_server_loop.clone(),
+            clients: self.clients.clone(),
         }
     }
 
@@ -130,6 +134,7 @@ impl Server {
 #[derive(Debug, Clone)]
 pub(super) struct ServerHandle {
     cancel_token: CancellationToken,
+    clients: Clients,
 }
 
 impl ServerHandle {
@@ -137,6 +142,11 @@ impl ServerHandle {
     pub(super) fn shutdown(&self) {
         self.cancel_token.cancel()
     }
+
+    /// Returns the traffic statistics of all currently connected clients.
+    pub(super) fn client_stats(&self) -> Vec<ClientStats> {
+        self.clients.stats()
+    }
 }
 
 /// Configuration to use for the TLS connection
@@ -230,6 +240,18 @@ pub enum AcceptError {
         #[snafu(implicit)]
         span_trace: n0_snafu::SpanTrace,
     },
+    #[snafu(display("Server at capacity, rejected client: {key:?}"))]
+    ServerAtCapacity {
+        key: PublicKey,
+        #[snafu(implicit)]
+        span_trace: n0_snafu::SpanTrace,
+    },
+    #[snafu(display("Too many connections, rejected client: {key:?}"))]
+    TooManyConnections {
+        key: PublicKey,
+        #[snafu(implicit)]
+        span_trace: n0_snafu::SpanTrace,
+    },
 }
 
 /// Server connection errors, includes errors that can happen on `accept`.
@@ -279,10 +301,16 @@ pub(super) struct ServerBuilder {
     /// Rate-limiting is enforced on received traffic from individual clients.  This
     /// configuration applies to a single client connection.
     client_rx_ratelimit: Option<ClientRateLimit>,
+    /// Maximum number of concurrently connected clients.
+    max_clients: Option<usize>,
+    /// Maximum number of concurrent connections from a single client.
+    max_connections_per_client: Option<usize>,
     /// The capacity of the key cache.
     key_cache_capacity: usize,
     /// Access config for nodes.
     access: AccessConfig,
+    /// The mesh peers to forward packets for unknown nodes to.
+    mesh: Option<Mesh>,
     metrics: Option<Arc<Metrics>>,
 }
 
@@ -295,8 +323,11 @@ impl ServerBuilder {
             handlers: Default::default(),
             headers: HeaderMap::new(),
             client_rx_ratelimit: None,
+            max_clients: None,
+            max_connections_per_client: None,
             key_cache_capacity: DEFAULT_KEY_CACHE_CAPACITY,
             access: AccessConfig::Everyone,
+            mesh: None,
             metrics: None,
         }
     }
@@ -313,6 +344,12 @@ impl ServerBuilder {
         self
     }
 
+    /// Forwards packets for nodes which are not connected to the mesh peers.
+    pub(super) fn mesh(mut self, mesh: Mesh) -> Self {
+        self.mesh = Some(mesh);
+        self
+    }
+
     /// Serves all requests content using TLS.
     pub(super) fn tls_config(mut self, config: Option<TlsConfig>) -> Self {
         self.tls_config = config;
@@ -328,6 +365,22 @@ impl ServerBuilder {
         self
     }
 
+    /// Sets the maximum number of concurrently connected clients.
+    ///
+    /// By default the number of clients is not limited.
+    pub(super) fn max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    /// Sets the maximum number of concurrent connections from a single client.
+    ///
+    /// By default the number of connections per client is not limited.
+    pub(super) fn max_connections_per_client(mut self, max_connections: usize) -> Self {
+        self.max_connections_per_client = Some(max_connections);
+        self
+    }
+
     /// Adds a custom handler for a specific Method & URI.
     pub(super) fn request_handler(
         mut self,
@@ -359,17 +412,21 @@ impl ServerBuilder {
 
         let cancel_token = CancellationToken::new();
 
-        let service = RelayService::new(
-            self.handlers,
-            self.headers,
-            self.client_rx_ratelimit,
-            KeyCache::new(self.key_cache_capacity),
-            self.access,
-            self.metrics.unwrap_or_default(),
-        );
+        let service = RelayService::new(ServiceConfig {
+            handlers: self.handlers,
+            headers: self.headers,
+            rate_limit: self.client_rx_ratelimit,
+            max_clients: self.max_clients,
+            max_connections_per_client: self.max_connections_per_client,
+            key_cache: KeyCache::new(self.key_cache_capacity),
+            access: self.access,
+            mesh: self.mesh,
+            metrics: self.metrics.unwrap_or_default(),
+        });
 
         let addr = self.addr;
         let tls_config = self.tls_config;
+        let clients = service.0.clients.clone();
 
         // Bind a TCP listener on `addr` and handles content using HTTPS.
 
@@ -428,6 +485,7 @@ impl ServerBuilder {
             addr,
             http_server_task: AbortOnDropHandle::new(task),
             cancel_server_loop: cancel_token,
+            clients,
         })
     }
 }
@@ -443,6 +501,8 @@ struct Inner {
     clients: Clients,
     write_timeout: Duration,
     rate_limit: Option<ClientRateLimit>,
+    max_clients: Option<usize>,
+    max_connections_per_client: Option<usize>,
     key_cache: KeyCache,
     access: AccessConfig,
     metrics: Arc<Metrics>,
@@ -656,10 +716,20 @@ impl Inner {
             }
         };
         trace!("accept: recv client key");
-        let (client_key, info) = recv_client_key(&mut io).await.context(RecvClientKeySnafu)?;
+        let (client_key, info) = recv_client_key(&mut io)
+            .await
+            .inspect_err(|_| {
+                self.metrics.handshake_failures.inc();
+            })
+            .context(RecvClientKeySnafu)?;
+
+        // Mesh peers are trusted relay servers: they bypass access control, capacity and
+        // rate limits.
+        let is_mesh_peer = self.clients.is_mesh_peer(&client_key);
 
         trace!("accept: checking access: {:?}", self.access);
-        if !self.access.is_allowed(client_key).await {
+        if !is_mesh_peer && !self.access.is_allowed(client_key).await {
+            self.metrics.conns_rejected_access_total.inc();
             io.send(Frame::Health {
                 problem: Bytes::from_static(b"not authenticated"),
             })
@@ -670,6 +740,7 @@ impl Inner {
         }
 
         if info.version != PROTOCOL_VERSION {
+            self.metrics.handshake_failures.inc();
             return Err(UnexpectedClientVersionSnafu {
                 version: info.version,
                 expected_version: PROTOCOL_VERSION,
@@ -677,13 +748,33 @@ impl Inner {
             .build());
         }
 
+        let connection = if is_mesh_peer {
+            None
+        } else {
+            let Some(connection) = self
+                .clients
+                .add_connection(client_key, self.max_connections_per_client)
+            else {
+                self.metrics.conns_rejected_per_client_total.inc();
+                io.send(Frame::Health {
+                    problem: Bytes::from_static(b"too many connections"),
+                })
+                .await?;
+                io.flush().await?;
+
+                return Err(TooManyConnectionsSnafu { key: client_key }.build());
+            };
+            Some(connection)
+        };
+
         trace!("accept: build client conn");
         let client_conn_builder = Config {
             node_id: client_key,
             stream: io,
             write_timeout: self.write_timeout,
             channel_capacity: PER_CLIENT_SEND_QUEUE_DEPTH,
-            rate_limit: self.rate_limit,
+            rate_limit: self.rate_limit.filter(|_| !is_mesh_peer),
+            connection,
         };
         trace!("accept: create client");
         let node_id = client_conn_builder.node_id;
@@ -691,9 +782,22 @@ impl Inner {
 
         // build and register client, starting up read & write loops for the client
         // connection
-        self.clients
-            .register(client_conn_builder, self.metrics.clone())
-            .await;
+        let max_clients = self.max_clients.filter(|_| !is_mesh_peer);
+        if let Err(config) = self
+            .clients
+            .register(client_conn_builder, max_clients, self.metrics.clone())
+            .await
+        {
+            self.metrics.conns_rejected_capacity_total.inc();
+            let mut io = config.stream;
+            io.send(Frame::Health {
+                problem: Bytes::from_static(b"server at capacity"),
+            })
+            .await?;
+            io.flush().await?;
+
+            return Err(ServerAtCapacitySnafu { key: node_id }.build());
+        }
         Ok(())
     }
 }
@@ -708,21 +812,41 @@ pub(super) enum TlsAcceptor {
     Manual(#[debug("tokio_rustls::TlsAcceptor")] tokio_rustls::TlsAcceptor),
 }
 
+/// Configuration for a [`RelayService`].
+#[derive(Debug)]
+struct ServiceConfig {
+    handlers: Handlers,
+    headers: HeaderMap,
+    rate_limit: Option<ClientRateLimit>,
+    max_clients: Option<usize>,
+    max_connections_per_client: Option<usize>,
+    key_cache: KeyCache,
+    access: AccessConfig,
+    mesh: Option<Mesh>,
+    metrics: Arc<Metrics>,
+}
+
 impl RelayService {
-    fn new(
-        handlers: Handlers,
-        headers: HeaderMap,
-        rate_limit: Option<ClientRateLimit>,
-        key_cache: KeyCache,
-        access: AccessConfig,
-        metrics: Arc<Metrics>,
-    ) -> Self {
+    fn new(config: ServiceConfig) -> Self {
+        let ServiceConfig {
+            handlers,
+            headers,
+            rate_limit,
+            max_clients,
+            max_connections_per_client,
+            key_cache,
+            access,
+            mesh,
+            metrics,
+        } = config;
         Self(Arc::new(Inner {
             handlers,
             headers,
-            clients: Clients::default(),
+            clients: Clients::new(mesh),
             write_timeout: SERVER_WRITE_TIMEOUT,
             rate_limit,
+            max_clients,
+            max_connections_per_client,
             key_cache,
             access,
             metrics,
@@ -1084,6 +1208,20 @@ mod tests {
         Ok(())
     }
 
+    fn test_config() -> ServiceConfig {
+        ServiceConfig {
+            handlers: Default::default(),
+            headers: Default::default(),
+            rate_limit: None,
+            max_clients: None,
+            max_connections_per_client: None,
+            key_cache: KeyCache::test(),
+            access: AccessConfig::Everyone,
+            mesh: None,
+            metrics: Default::default(),
+        }
+    }
+
     async fn make_test_client(client: tokio::io::DuplexStream, key: &SecretKey) -> Result<Conn> {
         let client = MaybeTlsStreamChained::Mem(client);
         let client = Conn::new_relay(client, KeyCache::test(), key).await?;
@@ -1094,14 +1232,7 @@ mod tests {
     #[traced_test]
     async fn test_server_basic() -> Result {
         info!("Create the server.");
-        let service = RelayService::new(
-            Default::default(),
-            Default::default(),
-            None,
-            KeyCache::test(),
-            AccessConfig::Everyone,
-            Default::default(),
-        );
+        let service = RelayService::new(test_config());
 
         info!("Create client A and connect it to the server.");
         let key_a = SecretKey::generate(rand::thread_rng());
@@ -1182,14 +1313,7 @@ mod tests {
     #[tokio::test]
     async fn test_server_replace_client() -> Result {
         info!("Create the server.");
-        let service = RelayService::new(
-            Default::default(),
-            Default::default(),
-            None,
-            KeyCache::test(),
-            AccessConfig::Everyone,
-            Default::default(),
-        );
+        let service = RelayService::new(test_config());
 
         info!("Create client A and connect it to the server.");
         let key_a = SecretKey::generate(rand::thread_rng());
@@ -1313,4 +1437,128 @@ mod tests {
         assert!(new_client_b.next().await.is_none());
         Ok(())
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_server_max_clients() -> Result {
+        let metrics = Arc::new(Metrics::default());
+        let service = RelayService::new(ServiceConfig {
+            max_clients: Some(1),
+            metrics: metrics.clone(),
+            ..test_config()
+        });
+
+        info!("Client A takes the only slot.");
+        let key_a = SecretKey::generate(rand::thread_rng());
+        let (client_a, rw_a) = tokio::io::duplex(10);
+        let s = service.clone();
+        let handler_task = tokio::spawn(async move {
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_a))
+                .await
+        });
+        let _client_a = make_test_client(client_a, &key_a).await?;
+        handler_task.await.context("join")??;
+
+        info!("Client B is rejected.");
+        let key_b = SecretKey::generate(rand::thread_rng());
+        let (client_b, rw_b) = tokio::io::duplex(64);
+        let s = service.clone();
+        let handler_task = tokio::spawn(async move {
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_b))
+                .await
+        });
+        let _client_b = make_test_client(client_b, &key_b).await?;
+        let res = handler_task.await.context("join")?;
+        assert!(matches!(res, Err(AcceptError::ServerAtCapacity { .. })));
+        assert_eq!(metrics.conns_rejected_capacity_total.get(), 1);
+
+        info!("Client A can still reconnect.");
+        let (new_client_a, new_rw_a) = tokio::io::duplex(10);
+        let s = service.clone();
+        let handler_task = tokio::spawn(async move {
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(new_rw_a))
+                .await
+        });
+        let _new_client_a = make_test_client(new_client_a, &key_a).await?;
+        handler_task.await.context("join")??;
+        assert_eq!(service.0.clients.len(), 1);
+
+        service.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_server_max_connections_per_client() -> Result {
+        let metrics = Arc::new(Metrics::default());
+        let service = RelayService::new(ServiceConfig {
+            max_connections_per_client: Some(1),
+            metrics: metrics.clone(),
+            ..test_config()
+        });
+
+        info!("Client A connects.");
+        let key_a = SecretKey::generate(rand::thread_rng());
+        let (client_a, rw_a) = tokio::io::duplex(10);
+        let s = service.clone();
+        let handler_task = tokio::spawn(async move {
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_a))
+                .await
+        });
+        let client_a = make_test_client(client_a, &key_a).await?;
+        handler_task.await.context("join")??;
+        assert_eq!(service.0.clients.connections(&key_a.public()), 1);
+
+        info!("A reconnecting client A replaces its existing connection.");
+        let (second_a, second_rw_a) = tokio::io::duplex(10);
+        let s = service.clone();
+        let handler_task = tokio::spawn(async move {
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(second_rw_a))
+                .await
+        });
+        let _second_a = make_test_client(second_a, &key_a).await?;
+        handler_task.await.context("join")??;
+        drop(client_a);
+
+        info!("A connection of client A beyond the limit is rejected.");
+        let pending = service
+            .0
+            .clients
+            .add_connection(key_a.public(), None)
+            .context("pending connection")?;
+        let (third_a, third_rw_a) = tokio::io::duplex(64);
+        let s = service.clone();
+        let handler_task = tokio::spawn(async move {
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(third_rw_a))
+                .await
+        });
+        let _third_a = make_test_client(third_a, &key_a).await?;
+        let res = handler_task.await.context("join")?;
+        assert!(matches!(res, Err(AcceptError::TooManyConnections { .. })));
+        assert_eq!(metrics.conns_rejected_per_client_total.get(), 1);
+
+        info!("Client B is not affected.");
+        let key_b = SecretKey::generate(rand::thread_rng());
+        let (client_b, rw_b) = tokio::io::duplex(10);
+        let s = service.clone();
+        let handler_task = tokio::spawn(async move {
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_b))
+                .await
+        });
+        let _client_b = make_test_client(client_b, &key_b).await?;
+        handler_task.await.context("join")??;
+
+        info!("Once the pending connection closes its slot is released.");
+        drop(pending);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while service.0.clients.connections(&key_a.public()) > 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("timeout")?;
+
+        service.shutdown().await;
+        Ok(())
+    }
 }
@@ -0,0 +1,340 @@
+//! Forwarding of packets between meshed relay servers.
+//!
+//! A set of relay servers can be federated into a mesh.  Each relay server announces the
+//! clients connected to it to all its mesh peers.  When a client sends a packet to a node
+//! which is not connected to this relay server, the packet is forwarded to the mesh peer
+//! which announced that node.  A mesh peer delivers a forwarded packet only if the
+//! destination node is connected to it, forwarded packets are never forwarded again.
+//!
+//! Trust between relay operators is established using node ids: each relay server in the
+//! mesh has a mesh [`SecretKey`] and connects to its peers like a normal relay client would.
+//! A relay server only accepts forwarded packets and presence announcements from
+//! connections which authenticated with one of the [`NodeId`]s configured in its
+//! [`MeshConfig::peers`].
+//!
+//! Mesh peers are trusted: their connections bypass the [`AccessConfig`], the
+//! [`Limits::max_clients`] and [`Limits::max_connections_per_client`] limits and the client
+//! rate limits, since they carry the traffic of many clients.  Only configure relay servers
+//! you operate or trust as mesh peers.
+//!
+//! [`AccessConfig`]: super::AccessConfig
+//! [`Limits::max_clients`]: super::Limits::max_clients
+//! [`Limits::max_connections_per_client`]: super::Limits::max_connections_per_client
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use iroh_base::{NodeId, RelayUrl, SecretKey};
+use n0_future::{
+    split::{split, SplitSink},
+    SinkExt, StreamExt,
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info_span, trace, warn, Instrument};
+
+use super::metrics::Metrics;
+use crate::{
+    client::{conn::Conn, ClientBuilder, ReceivedMessage},
+    dns::DnsResolver,
+    protos::relay::Frame,
+};
+
+/// The number of packets buffered per mesh peer while forwarding.
+const MESH_PEER_QUEUE_DEPTH: usize = 512;
+
+/// The initial delay before reconnecting to a mesh peer.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// The maximum delay before reconnecting to a mesh peer.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Configuration for federating relay servers into a mesh.
+#[derive(Debug, Clone)]
+pub struct MeshConfig {
+    /// The secret key this relay server uses to authenticate to its mesh peers.
+    ///
+    /// The corresponding [`NodeId`] needs to be configured as a [`MeshPeer`] on all the
+    /// other relay servers of the mesh.
+    pub secret_key: SecretKey,
+    /// The other relay servers in the mesh.
+    ///
+    /// Connections from these peers bypass access control, capacity and rate limits.
+    pub peers: Vec<MeshPeer>,
+}
+
+/// A relay server which is part of the mesh.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeshPeer {
+    /// The URL of the peer relay server, used to forward packets to it.
+    pub url: RelayUrl,
+    /// The [`NodeId`] the peer relay server authenticates with when forwarding packets to us.
+    pub node_id: NodeId,
+}
+
+/// A packet forwarded to a mesh peer.
+#[derive(Debug)]
+struct ForwardedPacket {
+    src: NodeId,
+    dst: NodeId,
+    data: Bytes,
+}
+
+/// Announces whether a client is connected to this relay server.
+#[derive(Debug)]
+struct Presence {
+    node_id: NodeId,
+    present: bool,
+}
+
+/// Handle to forward packets to the mesh peers.
+#[derive(Debug, Clone)]
+pub(super) struct Mesh(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    /// The links to the mesh peers, by the node id of the peer.
+    links: HashMap<NodeId, Link>,
+    /// The mesh peer each remote node is connected to, as announced by the peers.
+    routes: DashMap<NodeId, NodeId>,
+    /// The clients connected to this relay server, announced to the mesh peers.
+    local: Arc<Mutex<BTreeSet<NodeId>>>,
+    metrics: Arc<Metrics>,
+    cancel: CancellationToken,
+}
+
+/// The queues of the link to a single mesh peer.
+#[derive(Debug)]
+struct Link {
+    packets: mpsc::Sender<ForwardedPacket>,
+    presence: mpsc::UnboundedSender<Presence>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Mesh {
+    /// Creates the mesh and spawns the tasks maintaining the connections to the mesh peers.
+    pub(super) fn spawn(config: MeshConfig, metrics: Arc<Metrics>) -> Self {
+        let cancel = CancellationToken::new();
+        let dns_resolver = DnsResolver::new();
+        let local: Arc<Mutex<BTreeSet<NodeId>>> = Default::default();
+        let mut links = HashMap::new();
+        for peer in config.peers {
+            let (packets_tx, packets_rx) = mpsc::channel(MESH_PEER_QUEUE_DEPTH);
+            let (presence_tx, presence_rx) = mpsc::unbounded_channel();
+            links.insert(
+                peer.node_id,
+                Link {
+                    packets: packets_tx,
+                    presence: presence_tx,
+                },
+            );
+            let link = MeshLink {
+                secret_key: config.secret_key.clone(),
+                dns_resolver: dns_resolver.clone(),
+                local: local.clone(),
+                metrics: metrics.clone(),
+            };
+            let span = info_span!("mesh-link", peer = %peer.url);
+            tokio::spawn(
+                cancel
+                    .child_token()
+                    .run_until_cancelled_owned(link.run(peer.url, packets_rx, presence_rx))
+                    .instrument(span),
+            );
+        }
+        Self(Arc::new(Inner {
+            links,
+            routes: Default::default(),
+            local,
+            metrics,
+            cancel,
+        }))
+    }
+
+    /// Returns whether the node is one of the mesh peers.
+    pub(super) fn is_peer(&self, node_id: &NodeId) -> bool {
+        self.0.links.contains_key(node_id)
+    }
+
+    /// Announces to all mesh peers that a client connected to this relay server.
+    pub(super) fn client_connected(&self, node_id: NodeId) {
+        self.announce(node_id, true);
+    }
+
+    /// Announces to all mesh peers that a client disconnected from this relay server.
+    pub(super) fn client_disconnected(&self, node_id: NodeId) {
+        self.announce(node_id, false);
+    }
+
+    fn announce(&self, node_id: NodeId, present: bool) {
+        // Queue the announcements while holding the lock, so that a link which snapshots
+        // the local clients on connecting sees every change either in the snapshot or in
+        // its queue.
+        let mut local = self.0.local.lock().expect("poisoned");
+        let changed = if present {
+            local.insert(node_id)
+        } else {
+            local.remove(&node_id)
+        };
+        if changed {
+            for link in self.0.links.values() {
+                link.presence.send(Presence { node_id, present }).ok();
+            }
+        }
+    }
+
+    /// Records a presence announcement received from the mesh peer `peer`.
+    pub(super) fn update_route(&self, peer: NodeId, node_id: NodeId, present: bool) {
+        if present {
+            self.0.routes.insert(node_id, peer);
+        } else {
+            self.0.routes.remove_if(&node_id, |_, p| *p == peer);
+        }
+    }
+
+    /// Forgets all routes announced by `peer`, once its connection to us closed.
+    pub(super) fn remove_peer(&self, peer: &NodeId) {
+        self.0.routes.retain(|_, p| p != peer);
+    }
+
+    /// Forwards a packet for a node which is not connected to this relay server.
+    ///
+    /// The packet is forwarded to the mesh peer which announced `dst`.  Returns `false`
+    /// if no mesh peer announced `dst`, in which case the packet is not forwarded.
+    pub(super) fn forward(&self, src: NodeId, dst: NodeId, data: Bytes) -> bool {
+        let Some(peer) = self.0.routes.get(&dst).map(|peer| *peer) else {
+            return false;
+        };
+        let Some(link) = self.0.links.get(&peer) else {
+            return false;
+        };
+        match link.packets.try_send(ForwardedPacket { src, dst, data }) {
+            Ok(()) => {
+                self.0.metrics.mesh_packets_forwarded.inc();
+            }
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                debug!(peer = peer.fmt_short(), "mesh link busy, dropping packet");
+                self.0.metrics.mesh_packets_dropped.inc();
+            }
+        }
+        true
+    }
+}
+
+/// Maintains the connection to a single mesh peer.
+#[derive(Debug)]
+struct MeshLink {
+    secret_key: SecretKey,
+    dns_resolver: DnsResolver,
+    local: Arc<Mutex<BTreeSet<NodeId>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeshLink {
+    async fn run(
+        self,
+        url: RelayUrl,
+        mut packets: mpsc::Receiver<ForwardedPacket>,
+        mut presence: mpsc::UnboundedReceiver<Presence>,
+    ) {
+        let builder = ClientBuilder::new(url, self.secret_key.clone(), self.dns_resolver.clone());
+        let mut reconnect_delay = INITIAL_RECONNECT_DELAY;
+        loop {
+            let client = match builder.connect().await {
+                Ok(client) => client,
+                Err(err) => {
+                    debug!("failed to connect to mesh peer: {err:#}");
+                    // Do not let packets for this peer pile up while it is unreachable.
+                    while let Ok(_packet) = packets.try_recv() {
+                        self.metrics.mesh_packets_dropped.inc();
+                    }
+                    tokio::time::sleep(reconnect_delay).await;
+                    reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                    continue;
+                }
+            };
+            debug!("connected to mesh peer");
+            reconnect_delay = INITIAL_RECONNECT_DELAY;
+            let (mut sink, mut stream): (SplitSink<Conn, Frame>, _) = split(client.into_conn());
+
+            // The snapshot replaces all announcements queued so far.
+            let snapshot = {
+                let local = self.local.lock().expect("poisoned");
+                while presence.try_recv().is_ok() {}
+                local.clone()
+            };
+            if let Err(err) = self.announce_all(&mut sink, snapshot).await {
+                debug!("failed to announce clients to mesh peer: {err:#}");
+                continue;
+            }
+
+            loop {
+                tokio::select! {
+                    packet = packets.recv() => {
+                        let Some(ForwardedPacket { src, dst, data }) = packet else {
+                            trace!("mesh closed");
+                            return;
+                        };
+                        let frame = Frame::ForwardPacket { src_key: src, dst_key: dst, packet: data };
+                        if let Err(err) = sink.send(frame).await {
+                            warn!("failed to forward packet to mesh peer: {err:#}");
+                            self.metrics.mesh_packets_dropped.inc();
+                            break;
+                        }
+                    }
+                    Some(Presence { node_id, present }) = presence.recv() => {
+                        if let Err(err) = sink.send(Frame::MeshPresence { node_id, present }).await {
+                            debug!("failed to announce client to mesh peer: {err:#}");
+                            break;
+                        }
+                    }
+                    msg = stream.next() => match msg {
+                        Some(Ok(ReceivedMessage::Ping(data))) => {
+                            if let Err(err) = sink.send(Frame::Pong { data }).await {
+                                debug!("failed to send pong to mesh peer: {err:#}");
+                                break;
+                            }
+                        }
+                        Some(Ok(msg)) => {
+                            trace!(?msg, "ignoring message from mesh peer");
+                        }
+                        Some(Err(err)) => {
+                            debug!("connection to mesh peer failed: {err:#}");
+                            break;
+                        }
+                        None => {
+                            debug!("connection to mesh peer closed");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Announces all the clients connected to this relay server.
+    async fn announce_all(
+        &self,
+        sink: &mut SplitSink<Conn, Frame>,
+        nodes: BTreeSet<NodeId>,
+    ) -> Result<(), crate::client::SendError> {
+        for node_id in nodes {
+            sink.feed(Frame::MeshPresence {
+                node_id,
+                present: true,
+            })
+            .await?;
+        }
+        sink.flush().await
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use iroh_metrics::{Counter, MetricsGroup, MetricsGroupSet};
+use iroh_metrics::{Counter, Gauge, MetricsGroup, MetricsGroupSet};
 
 /// Metrics tracked for the relay server
 #[derive(Debug, Default, MetricsGroup)]
@@ -60,6 +60,24 @@ pub struct Metrics {
     pub frames_rx_ratelimited_total: Counter,
     /// Number of client connections which have had any frames rate-limited.
     pub conns_rx_ratelimited_total: Counter,
+    /// Number of client connections rejected because the server was at capacity.
+    pub conns_rejected_capacity_total: Counter,
+    /// Number of client connections rejected because the client had too many connections.
+    pub conns_rejected_per_client_total: Counter,
+    /// Number of client connections rejected by the access configuration.
+    pub conns_rejected_access_total: Counter,
+    /// Number of client connections which failed the relay protocol handshake.
+    pub handshake_failures: Counter,
+
+    /*
+     * Metrics about meshing
+     */
+    /// Number of packets forwarded to mesh peers.
+    pub mesh_packets_forwarded: Counter,
+    /// Number of packets received from mesh peers.
+    pub mesh_packets_recv: Counter,
+    /// Number of packets dropped while forwarding to, or delivering from, mesh peers.
+    pub mesh_packets_dropped: Counter,
 
     /*
      * Metrics about peers
@@ -70,6 +88,9 @@ pub struct Metrics {
     #[metrics(help = "Number of clients that have then disconnected.")]
     pub disconnects: Counter,
 
+    /// Number of currently connected clients.
+    pub clients_connected: Gauge,
+
     /// Number of unique client keys per day
     pub unique_client_keys: Counter,
 
@@ -77,6 +98,9 @@ pub struct Metrics {
     pub websocket_accepts: Counter,
     /// Number of accepted 'iroh derp http' connection upgrades
     pub relay_accepts: Counter,
+
+    /// Number of QUIC address discovery connections handled.
+    pub qad_conns: Counter,
     // TODO: enable when we can have multiple connections for one node id
     // pub duplicate_client_keys: Counter,
     // pub duplicate_client_conns: Counter,
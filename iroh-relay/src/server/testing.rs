@@ -59,6 +59,8 @@ pub fn relay_config() -> RelayConfig<()> {
         limits: Default::default(),
         key_cache_capacity: Some(1024),
         access: AccessConfig::Everyone,
+        mesh: None,
+        admin_bind_addr: None,
     }
 }
 
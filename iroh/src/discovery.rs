@@ -37,6 +37,9 @@
 //! - [`MdnsDiscovery`]: mdns::MdnsDiscovery which uses the crate `swarm-discovery`, an
 //!   opinionated mDNS implementation, to discover nodes on the local network.
 //!
+//! - The [`FileProvider`] which reads addressing information from a local file and watches
+//!   it for changes.
+//!
 //! - The [`DhtDiscovery`] also uses the [`pkarr`] system but can also publish and lookup
 //!   records to/from the Mainline DHT.
 //!
@@ -108,8 +111,12 @@
 //! [pkarr relay servers]: https://pkarr.org/#servers
 //! [`MdnsDiscovery`]: mdns::MdnsDiscovery
 //! [`StaticProvider`]: static_provider::StaticProvider
+//! [`FileProvider`]: file_provider::FileProvider
 
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, RwLock},
+};
 
 use iroh_base::{NodeAddr, NodeId};
 use n0_future::{
@@ -126,12 +133,14 @@ use tracing::{debug, error_span, warn, Instrument};
 
 #[cfg(not(wasm_browser))]
 use crate::dns::DnsResolver;
-pub use crate::node_info::{NodeData, NodeInfo, ParseError, UserData};
+pub use crate::node_info::{AttributeError, Attributes, NodeData, NodeInfo, ParseError, UserData};
 use crate::{Endpoint, SecretKey};
 
 #[cfg(not(wasm_browser))]
 pub mod dns;
 
+#[cfg(all(feature = "discovery-file", not(wasm_browser)))]
+pub mod file_provider;
 #[cfg(feature = "discovery-local-network")]
 pub mod mdns;
 pub mod pkarr;
@@ -355,6 +364,23 @@ pub trait Discovery: std::fmt::Debug + Send + Sync + 'static {
     fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
         None
     }
+
+    /// Subscribe to all [`DiscoveryEvent`]s of *passive* discovery.
+    ///
+    /// Besides the nodes yielded by [`Discovery::subscribe`], the stream may yield
+    /// [`DiscoveryEvent::Expired`] when a passively discovered node is no longer
+    /// available, e.g. because its mDNS announcement expired.
+    ///
+    /// The default implementation yields the items of [`Discovery::subscribe`] as
+    /// [`DiscoveryEvent::Discovered`].  Discovery systems which know when a node goes
+    /// away should implement this method in addition to `subscribe`.
+    ///
+    /// The [`crate::endpoint::Endpoint`] subscribes to this stream instead of
+    /// `subscribe`.
+    fn subscribe_events(&self) -> Option<BoxStream<DiscoveryEvent>> {
+        let stream = self.subscribe()?;
+        Some(Box::pin(stream.map(DiscoveryEvent::Discovered)))
+    }
 }
 
 impl<T: Discovery> Discovery for Arc<T> {}
@@ -446,12 +472,101 @@ impl From<DiscoveryItem> for NodeInfo {
     }
 }
 
+/// Events emitted by the [`Discovery::subscribe_events`] streams.
+///
+/// Passively observed events are also available from [`Endpoint::discovery_events`].
+#[derive(Debug, Clone)]
+pub enum DiscoveryEvent {
+    /// A node was discovered, or its addressing information changed.
+    Discovered(DiscoveryItem),
+    /// A previously discovered node is no longer available from a discovery service.
+    Expired {
+        /// The node which is no longer available.
+        node_id: NodeId,
+        /// The provenance of the discovery service which discovered the node.
+        provenance: &'static str,
+    },
+}
+
+impl DiscoveryEvent {
+    /// Returns the node id this event is about.
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            Self::Discovered(item) => item.node_id(),
+            Self::Expired { node_id, .. } => *node_id,
+        }
+    }
+
+    /// Returns the provenance of the discovery service which emitted this event.
+    pub fn provenance(&self) -> &'static str {
+        match self {
+            Self::Discovered(item) => item.provenance(),
+            Self::Expired { provenance, .. } => provenance,
+        }
+    }
+}
+
+impl From<DiscoveryItem> for DiscoveryEvent {
+    fn from(item: DiscoveryItem) -> Self {
+        Self::Discovered(item)
+    }
+}
+
+/// Options for a service in a [`ConcurrentDiscovery`].
+///
+/// The default options resolve the service with priority `0` and without a timeout.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiscoveryOptions {
+    /// The priority of the service.
+    ///
+    /// Services with the same priority resolve concurrently.  Services with a lower
+    /// priority are only used if none of the services with a higher priority found any
+    /// addressing information for the node before their resolve streams ended or their
+    /// [`DiscoveryOptions::timeout`] expired.
+    pub priority: i32,
+    /// The maximum time to wait for results from the service.
+    ///
+    /// The service's resolve stream is stopped once the timeout expires, which allows
+    /// services with a lower priority to be tried.  If not set, services with a lower
+    /// priority are tried if the service found nothing within 5 seconds, as many services
+    /// never end their resolve streams.
+    pub timeout: Option<Duration>,
+}
+
+impl DiscoveryOptions {
+    /// Sets the [`DiscoveryOptions::priority`].
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the [`DiscoveryOptions::timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
 /// A discovery service that combines multiple discovery sources.
 ///
-/// The discovery services will resolve concurrently.
+/// The discovery services will resolve concurrently, unless they were added with
+/// different [`DiscoveryOptions::priority`]: in that case the services resolve in tiers
+/// from the highest to the lowest priority, moving on to the next tier only if the
+/// previous one found nothing before its [`DiscoveryOptions::timeout`] expired.  Each [`DiscoveryItem`] keeps the provenance of the service
+/// which produced it.
+///
+/// Services can be added and removed while the discovery is in use.  Resolves which are
+/// already running are not affected by this, and services added later only contribute
+/// to [`Discovery::subscribe_events`] streams created after they were added.
 #[derive(Debug, Default)]
 pub struct ConcurrentDiscovery {
-    services: Vec<Box<dyn Discovery>>,
+    services: RwLock<Vec<DiscoveryService>>,
+}
+
+#[derive(Debug, Clone)]
+struct DiscoveryService {
+    service: Arc<dyn Discovery>,
+    options: DiscoveryOptions,
 }
 
 impl ConcurrentDiscovery {
@@ -462,12 +577,59 @@ impl ConcurrentDiscovery {
 
     /// Creates a new [`ConcurrentDiscovery`].
     pub fn from_services(services: Vec<Box<dyn Discovery>>) -> Self {
-        Self { services }
+        Self::from(services)
+    }
+
+    /// Adds a [`Discovery`] service with the default [`DiscoveryOptions`].
+    pub fn add(&self, service: impl Discovery + 'static) {
+        self.add_boxed(Box::new(service), DiscoveryOptions::default());
     }
 
-    /// Adds a [`Discovery`] service.
-    pub fn add(&mut self, service: impl Discovery + 'static) {
-        self.services.push(Box::new(service));
+    /// Adds a [`Discovery`] service with the given [`DiscoveryOptions`].
+    pub fn add_with_options(&self, service: impl Discovery + 'static, options: DiscoveryOptions) {
+        self.add_boxed(Box::new(service), options);
+    }
+
+    pub(crate) fn add_boxed(&self, service: Box<dyn Discovery>, options: DiscoveryOptions) {
+        self.services
+            .write()
+            .expect("poisoned")
+            .push(DiscoveryService {
+                service: service.into(),
+                options,
+            });
+    }
+
+    /// Removes all discovery services.
+    pub fn clear(&self) {
+        self.services.write().expect("poisoned").clear();
+    }
+
+    /// Returns the number of discovery services.
+    pub fn len(&self) -> usize {
+        self.services.read().expect("poisoned").len()
+    }
+
+    /// Returns `true` if there are no discovery services.
+    pub fn is_empty(&self) -> bool {
+        self.services.read().expect("poisoned").is_empty()
+    }
+
+    /// Returns the services grouped by priority, from the highest to the lowest.
+    fn tiers(&self) -> VecDeque<Vec<DiscoveryService>> {
+        let mut services = self.services.read().expect("poisoned").clone();
+        // The sort is stable, so services keep the order they were added in.
+        services.sort_by_key(|s| std::cmp::Reverse(s.options.priority));
+        let mut tiers: VecDeque<Vec<DiscoveryService>> = VecDeque::new();
+        for service in services {
+            match tiers.back_mut() {
+                Some(tier) if tier[0].options.priority == service.options.priority => {
+                    tier.push(service)
+                }
+                _ => tiers.push_back(vec![service]),
+            }
+        }
+        tiers
     }
 }
 
@@ -476,32 +638,111 @@ where
     T: IntoIterator<Item = Box<dyn Discovery>>,
 {
     fn from(iter: T) -> Self {
-        let services = iter.into_iter().collect::<Vec<_>>();
-        Self { services }
+        let services = iter
+            .into_iter()
+            .map(|service| DiscoveryService {
+                service: service.into(),
+                options: DiscoveryOptions::default(),
+            })
+            .collect();
+        Self {
+            services: RwLock::new(services),
+        }
     }
 }
 
 impl Discovery for ConcurrentDiscovery {
     fn publish(&self, data: &NodeData) {
-        for service in &self.services {
+        let services = self.services.read().expect("poisoned").clone();
+        for DiscoveryService { service, .. } in services {
             service.publish(data);
         }
     }
 
     fn resolve(&self, node_id: NodeId) -> Option<BoxStream<Result<DiscoveryItem, DiscoveryError>>> {
-        let streams = self
-            .services
-            .iter()
-            .filter_map(|service| service.resolve(node_id));
+        let mut tiers = self.tiers();
+        if tiers.len() <= 1 {
+            let tier = tiers.pop_front().unwrap_or_default();
+            return Some(resolve_tier(node_id, tier));
+        }
+        let state = ResolveState {
+            node_id,
+            tiers,
+            current: None,
+            found: false,
+        };
+        let stream = n0_future::stream::unfold(state, |mut state| async move {
+            loop {
+                let current = match state.current.as_mut() {
+                    Some(current) => current,
+                    None => {
+                        if state.found {
+                            return None;
+                        }
+                        let tier = state.tiers.pop_front()?;
+                        let deadline = if state.tiers.is_empty() {
+                            None
+                        } else {
+                            tier.iter()
+                                .map(|s| s.options.timeout.unwrap_or(DEFAULT_TIER_TIMEOUT))
+                                .max()
+                                .map(|timeout| time::Instant::now() + timeout)
+                        };
+                        state.current.insert(CurrentTier {
+                            stream: resolve_tier(state.node_id, tier),
+                            deadline,
+                        })
+                    }
+                };
+                let is_last = current.deadline.is_none();
+                let next = match current.deadline.filter(|_| !state.found) {
+                    Some(deadline) => {
+                        let remaining = deadline.duration_since(time::Instant::now());
+                        match time::timeout(remaining, current.stream.next()).await {
+                            Ok(next) => next,
+                            Err(_) => {
+                                debug!("discovery tier found nothing in time, trying next tier");
+                                state.current = None;
+                                continue;
+                            }
+                        }
+                    }
+                    None => current.stream.next().await,
+                };
+                match next {
+                    Some(Ok(item)) => {
+                        state.found = true;
+                        return Some((Ok(item), state));
+                    }
+                    Some(Err(err)) if is_last => return Some((Err(err), state)),
+                    Some(Err(err)) => {
+                        debug!(?err, "discovery service failed, trying next tier");
+                    }
+                    None => state.current = None,
+                }
+            }
+        });
+        Some(Box::pin(stream))
+    }
+
+    fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
+        let services = self.services.read().expect("poisoned").clone();
+        let mut streams = vec![];
+        for DiscoveryService { service, .. } in services {
+            if let Some(stream) = service.subscribe() {
+                streams.push(stream)
+            }
+        }
 
         let streams = n0_future::MergeBounded::from_iter(streams);
         Some(Box::pin(streams))
     }
 
-    fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
+    fn subscribe_events(&self) -> Option<BoxStream<DiscoveryEvent>> {
+        let services = self.services.read().expect("poisoned").clone();
         let mut streams = vec![];
-        for service in self.services.iter() {
-            if let Some(stream) = service.subscribe() {
+        for DiscoveryService { service, .. } in services {
+            if let Some(stream) = service.subscribe_events() {
                 streams.push(stream)
             }
         }
@@ -511,6 +752,60 @@ impl Discovery for ConcurrentDiscovery {
     }
 }
 
+/// The time to wait for results from a service without a [`DiscoveryOptions::timeout`]
+/// before trying the services with a lower priority.
+const DEFAULT_TIER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of a [`ConcurrentDiscovery::resolve`] stream going through the priority tiers.
+struct ResolveState {
+    node_id: NodeId,
+    tiers: VecDeque<Vec<DiscoveryService>>,
+    /// The current tier.
+    current: Option<CurrentTier>,
+    /// Whether any tier produced a result.
+    found: bool,
+}
+
+/// The tier of a [`ConcurrentDiscovery::resolve`] stream which is currently resolving.
+struct CurrentTier {
+    stream: BoxStream<Result<DiscoveryItem, DiscoveryError>>,
+    /// The time after which the next tier is tried if this tier found nothing.
+    ///
+    /// `None` for the last tier.
+    deadline: Option<time::Instant>,
+}
+
+/// Resolves the node concurrently on all services of a tier.
+fn resolve_tier(
+    node_id: NodeId,
+    tier: Vec<DiscoveryService>,
+) -> BoxStream<Result<DiscoveryItem, DiscoveryError>> {
+    let streams = tier.into_iter().filter_map(|s| {
+        let stream = s.service.resolve(node_id)?;
+        Some(match s.options.timeout {
+            Some(timeout) => with_timeout(stream, timeout),
+            None => stream,
+        })
+    });
+    Box::pin(n0_future::MergeBounded::from_iter(streams))
+}
+
+/// Ends the stream once the timeout expires.
+fn with_timeout<T: Send + 'static>(stream: BoxStream<T>, timeout: Duration) -> BoxStream<T> {
+    let deadline = time::Instant::now() + timeout;
+    let stream = n0_future::stream::unfold(stream, move |mut stream| async move {
+        let remaining = deadline.duration_since(time::Instant::now());
+        match time::timeout(remaining, stream.next()).await {
+            Ok(item) => Some((item?, stream)),
+            Err(_) => {
+                debug!("discovery service timed out");
+                None
+            }
+        }
+    });
+    Box::pin(stream)
+}
+
 /// Maximum duration since the last control or data message received from an endpoint to make us
 /// start a discovery task.
 const MAX_AGE: Duration = Duration::from_secs(10);
@@ -653,7 +948,8 @@ impl DiscoveryTask {
                         tx.send(Ok(())).ok();
                     }
                     // Send the discovery item to the subscribers of the discovery broadcast stream.
-                    ep.discovery_subscribers().send(r);
+                    ep.discovery_subscribers()
+                        .send(DiscoveryEvent::Discovered(r));
                 }
                 Some(Err(err)) => {
                     warn!(?err, "discovery service produced error");
@@ -685,13 +981,13 @@ pub struct Lagged {
 
 #[derive(Clone, Debug)]
 pub(super) struct DiscoverySubscribers {
-    inner: tokio::sync::broadcast::Sender<DiscoveryItem>,
+    inner: tokio::sync::broadcast::Sender<DiscoveryEvent>,
 }
 
 impl DiscoverySubscribers {
     pub(crate) fn new() -> Self {
         // TODO: Make capacity configurable from the endpoint builder?
-        // This is the maximum number of [`DiscoveryItem`]s held by the channel if
+        // This is the maximum number of [`DiscoveryEvent`]s held by the channel if
         // subscribers are stalled.
         const CAPACITY: usize = 128;
         Self {
@@ -699,13 +995,13 @@ impl DiscoverySubscribers {
         }
     }
 
-    pub(crate) fn subscribe(&self) -> impl Stream<Item = Result<DiscoveryItem, Lagged>> {
+    pub(crate) fn subscribe(&self) -> impl Stream<Item = Result<DiscoveryEvent, Lagged>> {
         use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
         let recv = self.inner.subscribe();
         BroadcastStream::new(recv).map_err(|BroadcastStreamRecvError::Lagged(n)| Lagged { val: n })
     }
 
-    pub(crate) fn send(&self, item: DiscoveryItem) {
+    pub(crate) fn send(&self, item: DiscoveryEvent) {
         // `broadcast::Sender::send` returns an error if the channel has no subscribers,
         // which we don't care about.
         self.inner.send(item).ok();
@@ -737,7 +1033,7 @@ mod tests {
     #[derive(Debug, Clone)]
     struct TestDiscoveryShared {
         nodes: Arc<Mutex<InfoStore>>,
-        watchers: tokio::sync::broadcast::Sender<DiscoveryItem>,
+        watchers: tokio::sync::broadcast::Sender<DiscoveryEvent>,
     }
 
     impl Default for TestDiscoveryShared {
@@ -771,7 +1067,15 @@ mod tests {
         }
 
         pub fn send_passive(&self, item: DiscoveryItem) {
-            self.watchers.send(item).ok();
+            self.watchers.send(DiscoveryEvent::Discovered(item)).ok();
+        }
+
+        pub fn send_expired(&self, node_id: NodeId, provenance: &'static str) {
+            let event = DiscoveryEvent::Expired {
+                node_id,
+                provenance,
+            };
+            self.watchers.send(event).ok();
         }
     }
 
@@ -832,6 +1136,14 @@ mod tests {
         }
 
         fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
+            let stream = self.subscribe_events()?.filter_map(|event| match event {
+                DiscoveryEvent::Discovered(item) => Some(item),
+                DiscoveryEvent::Expired { .. } => None,
+            });
+            Some(Box::pin(stream))
+        }
+
+        fn subscribe_events(&self) -> Option<BoxStream<DiscoveryEvent>> {
             let recv = self.shared.watchers.subscribe();
             let stream =
                 tokio_stream::wrappers::BroadcastStream::new(recv).filter_map(|item| item.ok());
@@ -853,6 +1165,28 @@ mod tests {
         }
     }
 
+    #[derive(Debug, Clone)]
+    struct PendingDiscovery;
+
+    /// A discovery which only implements [`Discovery::subscribe`].
+    #[derive(Debug, Clone)]
+    struct PassiveDiscovery(DiscoveryItem);
+
+    impl Discovery for PassiveDiscovery {
+        fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
+            Some(n0_future::stream::once(self.0.clone()).boxed())
+        }
+    }
+
+    impl Discovery for PendingDiscovery {
+        fn resolve(
+            &self,
+            _node_id: NodeId,
+        ) -> Option<BoxStream<Result<DiscoveryItem, DiscoveryError>>> {
+            Some(n0_future::stream::pending().boxed())
+        }
+    }
+
     const TEST_ALPN: &[u8] = b"n0/iroh/test";
 
     /// This is a smoke test for our discovery mechanism.
@@ -877,6 +1211,128 @@ mod tests {
         Ok(())
     }
 
+    /// Services with a lower priority are only resolved if the higher priorities found
+    /// nothing, services which hang are cut off by their timeout.
+    #[tokio::test]
+    #[traced_test]
+    async fn concurrent_discovery_priorities() -> Result {
+        let shared = TestDiscoveryShared::default();
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let disco = ConcurrentDiscovery::empty();
+        disco.add_with_options(
+            PendingDiscovery,
+            DiscoveryOptions::default()
+                .with_priority(2)
+                .with_timeout(Duration::from_millis(100)),
+        );
+        disco.add_with_options(EmptyDiscovery, DiscoveryOptions::default().with_priority(1));
+        disco.add(shared.create_discovery(node_id));
+        disco.add_with_options(
+            shared.create_lying_discovery(node_id),
+            DiscoveryOptions::default().with_priority(-1),
+        );
+        let addr: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        disco.publish(&NodeData::new(None, BTreeSet::from([addr])));
+
+        let stream = disco.resolve(node_id).context("no stream")?;
+        let items: Vec<DiscoveryItem> =
+            time::timeout(Duration::from_secs(5), StreamExt::try_collect(stream))
+                .await
+                .context("timeout")??;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].provenance(), "test-disco");
+        assert_eq!(
+            items[0].to_node_addr().direct_addresses,
+            BTreeSet::from([addr])
+        );
+
+        disco.clear();
+        assert!(disco.is_empty());
+        disco.add(EmptyDiscovery);
+        assert_eq!(disco.len(), 1);
+        Ok(())
+    }
+
+    /// Services with a lower priority are tried if a service with a higher priority and
+    /// without a timeout never ends its resolve stream.
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_discovery_priorities_pending() -> Result {
+        let shared = TestDiscoveryShared::default();
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let disco = ConcurrentDiscovery::empty();
+        disco.add_with_options(
+            PendingDiscovery,
+            DiscoveryOptions::default().with_priority(1),
+        );
+        disco.add(shared.create_discovery(node_id));
+        let addr: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        disco.publish(&NodeData::new(None, BTreeSet::from([addr])));
+
+        let mut stream = disco.resolve(node_id).context("no stream")?;
+        let item = time::timeout(DEFAULT_TIER_TIMEOUT * 2, stream.next())
+            .await
+            .context("timeout")?
+            .context("no item")??;
+        assert_eq!(item.provenance(), "test-disco");
+        Ok(())
+    }
+
+    /// A discovery service added to a running endpoint receives the data the endpoint
+    /// already published, also if the endpoint started without discovery.
+    #[tokio::test]
+    #[traced_test]
+    async fn endpoint_add_discovery_publishes() -> Result {
+        let shared = TestDiscoveryShared::default();
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        assert!(ep.discovery().is_none());
+        // wait for our address to be updated and thus published at least once
+        ep.node_addr().initialized().await?;
+
+        ep.add_discovery(
+            shared.create_discovery(ep.node_id()),
+            DiscoveryOptions::default(),
+        )?;
+        let (data, _) = shared.nodes.lock().unwrap()[&ep.node_id()].clone();
+        assert!(!data.direct_addresses().is_empty());
+
+        // Services added after clearing receive the current data.
+        ep.clear_discovery();
+        let shared2 = TestDiscoveryShared::default();
+        ep.add_discovery(
+            shared2.create_discovery(ep.node_id()),
+            DiscoveryOptions::default(),
+        )?;
+        assert!(shared2.nodes.lock().unwrap().contains_key(&ep.node_id()));
+        Ok(())
+    }
+
+    /// Discovery services which only implement [`Discovery::subscribe`] still produce
+    /// events.
+    #[tokio::test]
+    async fn concurrent_discovery_subscribe_events() -> Result {
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let item = DiscoveryItem::new(NodeInfo::new(node_id), "test-disco-passive", None);
+        let disco = ConcurrentDiscovery::from_services(vec![Box::new(PassiveDiscovery(item))]);
+
+        let items: Vec<_> = disco.subscribe().context("no stream")?.collect().await;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].node_id(), node_id);
+
+        let events: Vec<_> = disco
+            .subscribe_events()
+            .context("no stream")?
+            .collect()
+            .await;
+        assert_eq!(events.len(), 1);
+        assert!(
+            matches!(&events[0], DiscoveryEvent::Discovered(item) if item.node_id() == node_id)
+        );
+        Ok(())
+    }
+
     /// This test adds an empty discovery which provides no addresses.
     #[tokio::test]
     #[traced_test]
@@ -891,7 +1347,7 @@ mod tests {
             let secret = SecretKey::generate(rand::thread_rng());
             let disco1 = EmptyDiscovery;
             let disco2 = disco_shared.create_discovery(secret.public());
-            let mut disco = ConcurrentDiscovery::empty();
+            let disco = ConcurrentDiscovery::empty();
             disco.add(disco1);
             disco.add(disco2);
             new_endpoint(secret, disco).await
@@ -926,7 +1382,7 @@ mod tests {
             let disco1 = EmptyDiscovery;
             let disco2 = disco_shared.create_lying_discovery(secret.public());
             let disco3 = disco_shared.create_discovery(secret.public());
-            let mut disco = ConcurrentDiscovery::empty();
+            let disco = ConcurrentDiscovery::empty();
             disco.add(disco1);
             disco.add(disco2);
             disco.add(disco3);
@@ -1014,6 +1470,7 @@ mod tests {
         };
 
         let mut stream = ep1.discovery_stream();
+        let mut events = ep1.discovery_events();
 
         // wait for ep2 node addr to be updated and connect from ep1 -> discovery via resolve
         ep2.node_addr().initialized().await?;
@@ -1041,6 +1498,20 @@ mod tests {
         assert_eq!(item.node_id(), passive_node_id);
         assert_eq!(item.provenance(), "test-disco-passive");
 
+        // the events stream also yields expiry of passively discovered nodes
+        disco_shared.send_expired(passive_node_id, "test-disco-passive");
+        let mut expired = None;
+        while let Some(event) = tokio::time::timeout(Duration::from_secs(1), events.next())
+            .await
+            .expect("timeout")
+        {
+            if let DiscoveryEvent::Expired { node_id, .. } = event.expect("stream lagged") {
+                expired = Some(node_id);
+                break;
+            }
+        }
+        assert_eq!(expired, Some(passive_node_id));
+
         Ok(())
     }
 
@@ -1091,7 +1562,10 @@ mod tests {
 #[cfg(test)]
 mod test_dns_pkarr {
     use iroh_base::{NodeAddr, SecretKey};
-    use iroh_relay::{node_info::UserData, RelayMap};
+    use iroh_relay::{
+        node_info::{Attributes, UserData},
+        RelayMap,
+    };
     use n0_future::time::Duration;
     use n0_snafu::{Error, Result, ResultExt};
     use tokio_util::task::AbortOnDropHandle;
@@ -1155,7 +1629,8 @@ mod test_dns_pkarr {
             PkarrPublisher::builder(dns_pkarr_server.pkarr_url.clone()).build(secret_key);
         let user_data: UserData = "foobar".parse().unwrap();
         let data = NodeData::new(relay_url.clone(), Default::default())
-            .with_user_data(Some(user_data.clone()));
+            .with_user_data(Some(user_data.clone()))
+            .with_attributes(Attributes::new().with("service", "chat")?);
         // does not block, update happens in background task
         publisher.update_node_data(&data);
         // wait until our shared state received the update from pkarr publishing
@@ -1174,6 +1649,40 @@ mod test_dns_pkarr {
 
         assert_eq!(resolved.to_node_addr(), expected_addr);
         assert_eq!(resolved.user_data(), Some(&user_data));
+        // attributes are only published when enabled
+        assert!(resolved.attributes().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn pkarr_publish_dns_resolve_attributes() -> Result<()> {
+        let origin = "testdns.example".to_string();
+
+        let dns_pkarr_server = DnsPkarrServer::run_with_origin(origin.clone())
+            .await
+            .context("DnsPkarrServer")?;
+
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+
+        let resolver = DnsResolver::with_nameserver(dns_pkarr_server.nameserver);
+        let publisher = PkarrPublisher::builder(dns_pkarr_server.pkarr_url.clone())
+            .include_attributes(true)
+            .build(secret_key);
+        let attributes = Attributes::new().with("service", "chat")?;
+        let data = NodeData::new(
+            Some("https://relay.example".parse().unwrap()),
+            Default::default(),
+        )
+        .with_attributes(attributes.clone());
+        publisher.update_node_data(&data);
+        dns_pkarr_server
+            .on_node(&node_id, PUBLISH_TIMEOUT)
+            .await
+            .context("wait for on node update")?;
+        let resolved = resolver.lookup_node_by_id(&node_id, &origin).await?;
+        assert_eq!(resolved.attributes(), &attributes);
         Ok(())
     }
 
@@ -0,0 +1,415 @@
+//! A node discovery backed by a local file.
+//!
+//! Air-gapped and test deployments often know the addresses of all their nodes in advance.
+//! The [`FileProvider`] reads this addressing information from a TOML file and watches the
+//! file for changes, so nodes can be found without running any external services:
+//!
+//! ```toml
+//! [[nodes]]
+//! node_id = "ae58ff8833241ac82d6ff7611046ed67b5072d142c588d0063e942d9a75502b6"
+//! relay_url = "https://relay.example.com"
+//! direct_addresses = ["192.168.1.12:11204"]
+//! ```
+//!
+//! Files with a `.json` extension are read as JSON instead, with the same structure:
+//!
+//! ```json
+//! {
+//!   "nodes": [
+//!     {
+//!       "node_id": "ae58ff8833241ac82d6ff7611046ed67b5072d142c588d0063e942d9a75502b6",
+//!       "relay_url": "https://relay.example.com",
+//!       "direct_addresses": ["192.168.1.12:11204"]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Both `relay_url` and `direct_addresses` are optional.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use iroh_base::{NodeId, RelayUrl};
+use n0_future::{
+    boxed::BoxStream,
+    stream::{self, StreamExt},
+    task::{self, AbortOnDropHandle},
+    time::{self, Duration, SystemTime},
+};
+use nested_enum_utils::common_fields;
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use tracing::{debug, warn};
+
+use super::{static_provider::StaticProvider, Discovery, DiscoveryError, DiscoveryItem, NodeInfo};
+use crate::discovery::NodeData;
+
+/// How often the file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Errors loading the file of a [`FileProvider`].
+#[common_fields({
+    backtrace: Option<snafu::Backtrace>,
+    #[snafu(implicit)]
+    span_trace: n0_snafu::SpanTrace,
+})]
+#[allow(missing_docs)]
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum FileProviderError {
+    #[snafu(display("Failed to read {}", path.display()))]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Failed to parse {}", path.display()))]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[snafu(display("Failed to parse {}", path.display()))]
+    ParseJson {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// The format of the file, determined by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::Json,
+            _ => Self::Toml,
+        }
+    }
+}
+
+/// The contents of the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodesFile {
+    #[serde(default)]
+    nodes: Vec<NodeEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodeEntry {
+    node_id: NodeId,
+    relay_url: Option<url::Url>,
+    #[serde(default)]
+    direct_addresses: BTreeSet<SocketAddr>,
+}
+
+/// A node discovery which reads addressing information from a TOML or JSON file.
+///
+/// The file is checked for changes every few seconds and reloaded when it was modified.
+/// Nodes removed from the file are no longer resolved.  If the file fails to load, the
+/// previously loaded addressing information is kept.
+///
+/// See the [module documentation](self) for the file format.
+///
+/// # Examples
+///
+/// ```no_run
+/// use iroh::{discovery::file_provider::FileProvider, Endpoint};
+///
+/// # async fn wrapper() -> n0_snafu::Result<()> {
+/// let discovery = FileProvider::load("nodes.toml").await?;
+/// let ep = Endpoint::builder().add_discovery(discovery).bind().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileProvider {
+    inner: Arc<Inner>,
+    _watcher: Arc<AbortOnDropHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path: PathBuf,
+    format: Format,
+    nodes: StaticProvider,
+    state: Mutex<LoadState>,
+    /// Held while loading the file, so that an older version of the file can not
+    /// overwrite a newer one.
+    reload_lock: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct LoadState {
+    /// The modification time of the file when it was last loaded.
+    modified: Option<SystemTime>,
+    /// The length of the file when it was last loaded.
+    len: Option<u64>,
+    /// The nodes contained in the file when it was last loaded.
+    node_ids: HashSet<NodeId>,
+}
+
+impl FileProvider {
+    /// The provenance string for this discovery implementation.
+    ///
+    /// This is mostly used for debugging information and allows understanding the origin of
+    /// addressing information used by an iroh [`Endpoint`].
+    ///
+    /// [`Endpoint`]: crate::Endpoint
+    pub const PROVENANCE: &'static str = "file_discovery";
+
+    /// Loads the file and starts watching it for changes.
+    ///
+    /// Files with a `.json` extension are parsed as JSON, all others as TOML.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, FileProviderError> {
+        Self::load_with_poll_interval(path.as_ref(), POLL_INTERVAL).await
+    }
+
+    async fn load_with_poll_interval(
+        path: &Path,
+        poll_interval: Duration,
+    ) -> Result<Self, FileProviderError> {
+        let inner = Arc::new(Inner {
+            path: path.to_path_buf(),
+            format: Format::from_path(path),
+            nodes: StaticProvider::new(),
+            state: Default::default(),
+            reload_lock: Default::default(),
+        });
+        inner.reload().await?;
+        let watcher = task::spawn({
+            let inner = inner.clone();
+            async move {
+                loop {
+                    time::sleep(poll_interval).await;
+                    if let Err(err) = inner.reload_if_modified().await {
+                        warn!("failed to reload {}: {err:#}", inner.path.display());
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            inner,
+            _watcher: Arc::new(AbortOnDropHandle::new(watcher)),
+        })
+    }
+
+    /// Reloads the file immediately, without waiting for the next check for changes.
+    pub async fn reload(&self) -> Result<(), FileProviderError> {
+        self.inner.reload().await
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Returns the addressing information loaded for the given node ID.
+    pub fn get_node_info(&self, node_id: NodeId) -> Option<NodeInfo> {
+        self.inner.nodes.get_node_info(node_id)
+    }
+}
+
+impl Inner {
+    async fn reload_if_modified(&self) -> Result<(), FileProviderError> {
+        let _guard = self.reload_lock.lock().await;
+        let (modified, len) = metadata(&self.path).await;
+        {
+            let state = self.state.lock().expect("poisoned");
+            if modified.is_some() && modified == state.modified && len == state.len {
+                return Ok(());
+            }
+        }
+        self.load().await
+    }
+
+    async fn reload(&self) -> Result<(), FileProviderError> {
+        let _guard = self.reload_lock.lock().await;
+        self.load().await
+    }
+
+    /// Loads the file, must be called with the `reload_lock` held.
+    async fn load(&self) -> Result<(), FileProviderError> {
+        let (modified, len) = metadata(&self.path).await;
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .context(ReadSnafu { path: &self.path })?;
+        let file: NodesFile = match self.format {
+            Format::Toml => toml::from_str(&content).context(ParseSnafu { path: &self.path })?,
+            Format::Json => {
+                serde_json::from_str(&content).context(ParseJsonSnafu { path: &self.path })?
+            }
+        };
+
+        let mut state = self.state.lock().expect("poisoned");
+        let mut node_ids = HashSet::new();
+        for entry in file.nodes {
+            let data = NodeData::new(entry.relay_url.map(RelayUrl::from), entry.direct_addresses);
+            self.nodes
+                .set_node_info(NodeInfo::from_parts(entry.node_id, data));
+            node_ids.insert(entry.node_id);
+        }
+        for removed in state.node_ids.difference(&node_ids) {
+            self.nodes.remove_node_info(*removed);
+        }
+        debug!(nodes = node_ids.len(), "loaded {}", self.path.display());
+        *state = LoadState {
+            modified,
+            len,
+            node_ids,
+        };
+        Ok(())
+    }
+}
+
+/// Returns the modification time and the length of the file, if available.
+async fn metadata(path: &Path) -> (Option<SystemTime>, Option<u64>) {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) => (metadata.modified().ok(), Some(metadata.len())),
+        Err(_) => (None, None),
+    }
+}
+
+impl Discovery for FileProvider {
+    fn resolve(&self, node_id: NodeId) -> Option<BoxStream<Result<DiscoveryItem, DiscoveryError>>> {
+        let info = self.inner.nodes.get_node_info(node_id)?;
+        let last_updated = self
+            .inner
+            .state
+            .lock()
+            .expect("poisoned")
+            .modified
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_micros() as u64);
+        let item = DiscoveryItem::new(info, Self::PROVENANCE, last_updated);
+        Some(stream::iter(Some(Ok(item))).boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_base::SecretKey;
+    use n0_snafu::{Result, ResultExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_and_reload() -> Result {
+        let dir = tempfile::tempdir().e()?;
+        let path = dir.path().join("nodes.toml");
+        let node_a = SecretKey::generate(rand::thread_rng()).public();
+        let node_b = SecretKey::generate(rand::thread_rng()).public();
+        let content = format!(
+            r#"
+            [[nodes]]
+            node_id = "{node_a}"
+            relay_url = "https://relay.example.com"
+            direct_addresses = ["192.168.1.12:11204"]
+
+            [[nodes]]
+            node_id = "{node_b}"
+            "#
+        );
+        tokio::fs::write(&path, content).await.e()?;
+
+        let discovery = FileProvider::load(&path).await?;
+        let mut stream = discovery.resolve(node_a).context("not found")?;
+        let item = stream.next().await.context("no item")??;
+        assert_eq!(item.provenance(), FileProvider::PROVENANCE);
+        let addr = item.to_node_addr();
+        assert_eq!(
+            addr.relay_url,
+            Some("https://relay.example.com".parse().unwrap())
+        );
+        assert_eq!(
+            addr.direct_addresses,
+            BTreeSet::from(["192.168.1.12:11204".parse().unwrap()])
+        );
+        assert!(discovery.get_node_info(node_b).is_some());
+
+        // Removing a node from the file removes it from the discovery.
+        tokio::fs::write(&path, format!("[[nodes]]\nnode_id = \"{node_b}\"\n"))
+            .await
+            .e()?;
+        discovery.reload().await?;
+        assert!(discovery.resolve(node_a).is_none());
+        assert!(discovery.get_node_info(node_b).is_some());
+
+        // A broken file keeps the previous information.
+        tokio::fs::write(&path, "nodes = 1").await.e()?;
+        assert!(discovery.reload().await.is_err());
+        assert!(discovery.get_node_info(node_b).is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_json() -> Result {
+        let dir = tempfile::tempdir().e()?;
+        let path = dir.path().join("nodes.json");
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let content = format!(
+            r#"{{"nodes": [{{"node_id": "{node_id}", "direct_addresses": ["192.168.1.12:11204"]}}]}}"#
+        );
+        tokio::fs::write(&path, content).await.e()?;
+
+        let discovery = FileProvider::load(&path).await?;
+        let info = discovery.get_node_info(node_id).context("not found")?;
+        assert_eq!(
+            info.data.direct_addresses(),
+            &BTreeSet::from(["192.168.1.12:11204".parse().unwrap()])
+        );
+
+        // The extension decides the format, TOML is not accepted in a JSON file.
+        tokio::fs::write(&path, format!("[[nodes]]\nnode_id = \"{node_id}\"\n"))
+            .await
+            .e()?;
+        assert!(matches!(
+            discovery.reload().await,
+            Err(FileProviderError::ParseJson { .. })
+        ));
+        Ok(())
+    }
+
+    /// Changes to the file are picked up without calling [`FileProvider::reload`].
+    #[tokio::test]
+    async fn test_poll_for_changes() -> Result {
+        let dir = tempfile::tempdir().e()?;
+        let path = dir.path().join("nodes.toml");
+        let node_a = SecretKey::generate(rand::thread_rng()).public();
+        let node_b = SecretKey::generate(rand::thread_rng()).public();
+        tokio::fs::write(&path, format!("[[nodes]]\nnode_id = \"{node_a}\"\n"))
+            .await
+            .e()?;
+
+        let discovery =
+            FileProvider::load_with_poll_interval(&path, Duration::from_millis(10)).await?;
+        assert!(discovery.get_node_info(node_a).is_some());
+
+        tokio::fs::write(
+            &path,
+            format!(
+                "[[nodes]]\nnode_id = \"{node_b}\"\ndirect_addresses = [\"192.168.1.12:11204\"]\n"
+            ),
+        )
+        .await
+        .e()?;
+        time::timeout(Duration::from_secs(5), async {
+            while discovery.get_node_info(node_b).is_none() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("file change not picked up")?;
+        assert!(discovery.get_node_info(node_a).is_none());
+        Ok(())
+    }
+}
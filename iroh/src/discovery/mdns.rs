@@ -39,6 +39,7 @@ use derive_more::FromStr;
 use iroh_base::{NodeId, PublicKey};
 use n0_future::{
     boxed::BoxStream,
+    stream::StreamExt,
     task::{self, AbortOnDropHandle, JoinSet},
     time::{self, Duration},
 };
@@ -48,7 +49,7 @@ use tokio::sync::mpsc::{self, error::TrySendError};
 use tracing::{debug, error, info_span, trace, warn, Instrument};
 
 use super::{DiscoveryContext, DiscoveryError, IntoDiscovery, IntoDiscoveryError};
-use crate::discovery::{Discovery, DiscoveryItem, NodeData, NodeInfo};
+use crate::discovery::{Attributes, Discovery, DiscoveryEvent, DiscoveryItem, NodeData, NodeInfo};
 
 /// The n0 local swarm node discovery name
 const N0_LOCAL_SWARM: &str = "iroh.local.swarm";
@@ -64,6 +65,9 @@ pub const NAME: &str = "local.swarm.discovery";
 /// the TXT record supported by swarm-discovery.
 const USER_DATA_ATTRIBUTE: &str = "user-data";
 
+/// The key prefix of the TXT attributes carrying the application-defined [`Attributes`].
+const ATTRIBUTE_PREFIX: &str = "attr.";
+
 /// How long we will wait before we stop sending discovery items
 const DISCOVERY_DURATION: Duration = Duration::from_secs(10);
 
@@ -82,12 +86,12 @@ enum Message {
     Discovery(String, Peer),
     Resolve(NodeId, mpsc::Sender<Result<DiscoveryItem, DiscoveryError>>),
     Timeout(NodeId, usize),
-    Subscribe(mpsc::Sender<DiscoveryItem>),
+    Subscribe(mpsc::Sender<DiscoveryEvent>),
 }
 
 /// Manages the list of subscribers that are subscribed to this discovery service.
 #[derive(Debug)]
-struct Subscribers(Vec<mpsc::Sender<DiscoveryItem>>);
+struct Subscribers(Vec<mpsc::Sender<DiscoveryEvent>>);
 
 impl Subscribers {
     fn new() -> Self {
@@ -95,14 +99,14 @@ impl Subscribers {
     }
 
     /// Add the subscriber to the list of subscribers
-    fn push(&mut self, subscriber: mpsc::Sender<DiscoveryItem>) {
+    fn push(&mut self, subscriber: mpsc::Sender<DiscoveryEvent>) {
         self.0.push(subscriber);
     }
 
-    /// Sends the `node_id` and `item` to each subscriber.
+    /// Sends the `item` to each subscriber.
     ///
     /// Cleans up any subscribers that have been dropped.
-    fn send(&mut self, item: DiscoveryItem) {
+    fn send(&mut self, item: DiscoveryEvent) {
         let mut clean_up = vec![];
         for (i, subscriber) in self.0.iter().enumerate() {
             // assume subscriber was dropped
@@ -172,6 +176,7 @@ impl MdnsDiscovery {
                 HashMap<usize, mpsc::Sender<Result<DiscoveryItem, DiscoveryError>>>,
             > = HashMap::default();
             let mut timeouts = JoinSet::new();
+            let mut published_attributes = BTreeSet::new();
             loop {
                 trace!(?node_addrs, "MdnsDiscovery Service loop tick");
                 let msg = tokio::select! {
@@ -191,6 +196,21 @@ impl MdnsDiscovery {
                                 warn!("Failed to set the user-defined data in local swarm discovery: {err:?}");
                             }
                         }
+                        let attributes: BTreeSet<String> = data
+                            .attributes()
+                            .iter()
+                            .map(|(key, _)| format!("{ATTRIBUTE_PREFIX}{key}"))
+                            .collect();
+                        for key in published_attributes.difference(&attributes) {
+                            discovery.remove_txt_attribute(key.clone());
+                        }
+                        for (key, value) in data.attributes().iter() {
+                            let key = format!("{ATTRIBUTE_PREFIX}{key}");
+                            if let Err(err) = discovery.set_txt_attribute(key, Some(value.to_string())) {
+                                warn!("Failed to set an attribute in local swarm discovery: {err:?}");
+                            }
+                        }
+                        published_attributes = attributes;
                         continue;
                     }
                 };
@@ -230,7 +250,12 @@ impl MdnsDiscovery {
                                 ?discovered_node_id,
                                 "removing node from MdnsDiscovery address book"
                             );
-                            node_addrs.remove(&discovered_node_id);
+                            if node_addrs.remove(&discovered_node_id).is_some() {
+                                subscribers.send(DiscoveryEvent::Expired {
+                                    node_id: discovered_node_id,
+                                    provenance: NAME,
+                                });
+                            }
                             continue;
                         }
 
@@ -263,7 +288,7 @@ impl MdnsDiscovery {
                         // in other words, nodes sent to the `subscribers` should only be the ones that
                         // have been "passively" discovered
                         if !resolved {
-                            subscribers.send(item);
+                            subscribers.send(DiscoveryEvent::Discovered(item));
                         }
                     }
                     Message::Resolve(node_id, sender) => {
@@ -383,9 +408,18 @@ fn peer_to_discovery_item(peer: &Peer, node_id: &NodeId) -> DiscoveryItem {
     } else {
         None
     };
+    let mut attributes = Attributes::new();
+    for (key, value) in peer.txt_attributes() {
+        if let (Some(key), Some(value)) = (key.strip_prefix(ATTRIBUTE_PREFIX), value) {
+            if let Err(err) = attributes.insert(key, value) {
+                debug!("failed to parse attribute from TXT attribute: {err}");
+            }
+        }
+    }
     let node_info = NodeInfo::new(*node_id)
         .with_direct_addresses(direct_addresses)
-        .with_user_data(user_data);
+        .with_user_data(user_data)
+        .with_attributes(attributes);
     DiscoveryItem::new(node_info, NAME, None)
 }
 
@@ -410,6 +444,14 @@ impl Discovery for MdnsDiscovery {
     }
 
     fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
+        let stream = self.subscribe_events()?.filter_map(|event| match event {
+            DiscoveryEvent::Discovered(item) => Some(item),
+            DiscoveryEvent::Expired { .. } => None,
+        });
+        Some(Box::pin(stream))
+    }
+
+    fn subscribe_events(&self) -> Option<BoxStream<DiscoveryEvent>> {
         use futures_util::FutureExt;
 
         let (sender, recv) = mpsc::channel(20);
@@ -446,7 +488,8 @@ mod tests {
             // make addr info for discoverer b
             let user_data: UserData = "foobar".parse()?;
             let node_data = NodeData::new(None, BTreeSet::from(["0.0.0.0:11111".parse().unwrap()]))
-                .with_user_data(Some(user_data.clone()));
+                .with_user_data(Some(user_data.clone()))
+                .with_attributes(Attributes::new().with("service", "chat")?);
             println!("info {node_data:?}");
 
             // resolve twice to ensure we can create separate streams for the same node_id
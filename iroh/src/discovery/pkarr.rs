@@ -66,7 +66,7 @@ use super::{DiscoveryContext, DiscoveryError, IntoDiscovery, IntoDiscoveryError}
 #[cfg(not(wasm_browser))]
 use crate::dns::DnsResolver;
 use crate::{
-    discovery::{Discovery, DiscoveryItem, NodeData},
+    discovery::{Attributes, Discovery, DiscoveryItem, NodeData},
     endpoint::force_staging_infra,
 };
 
@@ -136,6 +136,7 @@ pub struct PkarrPublisherBuilder {
     pkarr_relay: Url,
     ttl: u32,
     republish_interval: Duration,
+    include_attributes: bool,
     #[cfg(not(wasm_browser))]
     dns_resolver: Option<DnsResolver>,
 }
@@ -147,6 +148,7 @@ impl PkarrPublisherBuilder {
             pkarr_relay,
             ttl: DEFAULT_PKARR_TTL,
             republish_interval: DEFAULT_REPUBLISH_INTERVAL,
+            include_attributes: false,
             #[cfg(not(wasm_browser))]
             dns_resolver: None,
         }
@@ -179,6 +181,17 @@ impl PkarrPublisherBuilder {
         self
     }
 
+    /// Sets whether to publish the application-defined [`Attributes`] of the node.
+    ///
+    /// Iroh versions which do not know about attributes fail to parse records containing
+    /// them, so nodes running these versions can no longer resolve this node.
+    ///
+    /// Default is `false`.
+    pub fn include_attributes(mut self, include_attributes: bool) -> Self {
+        self.include_attributes = include_attributes;
+        self
+    }
+
     /// Sets the DNS resolver to use for resolving the pkarr relay URL.
     #[cfg(not(wasm_browser))]
     pub fn dns_resolver(mut self, dns_resolver: DnsResolver) -> Self {
@@ -195,6 +208,7 @@ impl PkarrPublisherBuilder {
             self.pkarr_relay,
             self.ttl,
             self.republish_interval,
+            self.include_attributes,
             #[cfg(not(wasm_browser))]
             self.dns_resolver,
         )
@@ -233,6 +247,7 @@ impl IntoDiscovery for PkarrPublisherBuilder {
 #[derive(derive_more::Debug, Clone)]
 pub struct PkarrPublisher {
     node_id: NodeId,
+    include_attributes: bool,
     watchable: Watchable<Option<NodeInfo>>,
     _drop_guard: Arc<AbortOnDropHandle<()>>,
 }
@@ -262,6 +277,7 @@ impl PkarrPublisher {
         pkarr_relay: Url,
         ttl: u32,
         republish_interval: Duration,
+        include_attributes: bool,
         #[cfg(not(wasm_browser))] dns_resolver: Option<DnsResolver>,
     ) -> Self {
         debug!("creating pkarr publisher that publishes to {pkarr_relay}");
@@ -293,6 +309,7 @@ impl PkarrPublisher {
         Self {
             watchable,
             node_id,
+            include_attributes,
             _drop_guard: Arc::new(AbortOnDropHandle::new(join_handle)),
         }
     }
@@ -320,6 +337,9 @@ impl PkarrPublisher {
             // If relay url is set: only publish relay url, and no direct addrs.
             data.clear_direct_addresses();
         }
+        if !self.include_attributes {
+            data.set_attributes(Attributes::new());
+        }
         let info = NodeInfo::from_parts(self.node_id, data);
         self.watchable.set(Some(info)).ok();
     }
@@ -348,10 +368,8 @@ impl PublisherService {
         let mut failed_attempts = 0;
         let republish = time::sleep(Duration::MAX);
         tokio::pin!(republish);
-        loop {
-            let Ok(info) = self.watcher.get() else {
-                break; // disconnected
-            };
+        // Stops once the watcher is disconnected.
+        while let Ok(info) = self.watcher.get() {
             if let Some(info) = info {
                 if let Err(err) = self.publish_current(info).await {
                     failed_attempts += 1;
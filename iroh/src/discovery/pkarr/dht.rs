@@ -20,7 +20,7 @@ use url::Url;
 use crate::{
     discovery::{
         pkarr::{DEFAULT_PKARR_TTL, N0_DNS_PKARR_RELAY_PROD, N0_DNS_PKARR_RELAY_STAGING},
-        Discovery, DiscoveryContext, DiscoveryError, DiscoveryItem, IntoDiscovery,
+        Attributes, Discovery, DiscoveryContext, DiscoveryError, DiscoveryItem, IntoDiscovery,
         IntoDiscoveryError, NodeData,
     },
     node_info::NodeInfo,
@@ -73,6 +73,8 @@ struct Inner {
     ttl: u32,
     /// True to include the direct addresses in the DNS packet.
     include_direct_addresses: bool,
+    /// True to include the application-defined attributes in the DNS packet.
+    include_attributes: bool,
     /// Initial delay before the first publish.
     initial_publish_delay: Duration,
     /// Republish delay for the DHT.
@@ -121,6 +123,7 @@ pub struct Builder {
     pkarr_relay: Option<Url>,
     dht: bool,
     include_direct_addresses: bool,
+    include_attributes: bool,
     initial_publish_delay: Duration,
     republish_delay: Duration,
     enable_publish: bool,
@@ -135,6 +138,7 @@ impl Default for Builder {
             pkarr_relay: None,
             dht: true,
             include_direct_addresses: false,
+            include_attributes: false,
             initial_publish_delay: INITIAL_PUBLISH_DELAY,
             republish_delay: REPUBLISH_DELAY,
             enable_publish: true,
@@ -194,6 +198,15 @@ impl Builder {
         self
     }
 
+    /// Sets whether to include the application-defined [`Attributes`] in the DNS packet.
+    ///
+    /// Iroh versions which do not know about attributes fail to parse packets containing
+    /// them, so nodes running these versions can no longer resolve this node.
+    pub fn include_attributes(mut self, include_attributes: bool) -> Self {
+        self.include_attributes = include_attributes;
+        self
+    }
+
     /// Sets the initial delay before the first publish.
     pub fn initial_publish_delay(mut self, initial_publish_delay: Duration) -> Self {
         self.initial_publish_delay = initial_publish_delay;
@@ -230,7 +243,7 @@ impl Builder {
                 }
                 if let Some(url) = &self.pkarr_relay {
                     builder
-                        .relays(&[url.clone()])
+                        .relays(std::slice::from_ref(url))
                         .map_err(|e| IntoDiscoveryError::from_err("pkarr", e))?;
                 }
                 builder
@@ -247,6 +260,7 @@ impl Builder {
             ttl,
             relay_url: self.pkarr_relay,
             include_direct_addresses,
+            include_attributes: self.include_attributes,
             secret_key,
             initial_publish_delay: self.initial_publish_delay,
             republish_delay: self.republish_delay,
@@ -312,6 +326,9 @@ impl Discovery for DhtDiscovery {
         if !self.0.include_direct_addresses {
             info.clear_direct_addresses();
         }
+        if !self.0.include_attributes {
+            info.set_attributes(Attributes::new());
+        }
         let Ok(signed_packet) = info.to_pkarr_signed_packet(keypair, self.0.ttl) else {
             tracing::warn!("failed to create signed packet");
             return;
@@ -13,7 +13,7 @@
 
 use std::{
     any::Any,
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     future::{Future, IntoFuture},
     net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6},
     pin::Pin,
@@ -24,7 +24,7 @@ use std::{
 use ed25519_dalek::{pkcs8::DecodePublicKey, VerifyingKey};
 use iroh_base::{NodeAddr, NodeId, RelayUrl, SecretKey};
 use iroh_relay::RelayMap;
-use n0_future::{time::Duration, Stream};
+use n0_future::{time::Duration, Stream, StreamExt};
 use n0_watcher::Watcher;
 use nested_enum_utils::common_fields;
 use pin_project::pin_project;
@@ -38,13 +38,13 @@ use crate::discovery::pkarr::PkarrResolver;
 use crate::{discovery::dns::DnsDiscovery, dns::DnsResolver};
 use crate::{
     discovery::{
-        pkarr::PkarrPublisher, ConcurrentDiscovery, Discovery, DiscoveryContext, DiscoveryError,
-        DiscoveryItem, DiscoverySubscribers, DiscoveryTask, DynIntoDiscovery, IntoDiscovery,
-        IntoDiscoveryError, Lagged, UserData,
+        pkarr::PkarrPublisher, Attributes, ConcurrentDiscovery, Discovery, DiscoveryContext,
+        DiscoveryError, DiscoveryEvent, DiscoveryItem, DiscoveryOptions, DiscoverySubscribers,
+        DiscoveryTask, DynIntoDiscovery, IntoDiscovery, IntoDiscoveryError, Lagged, UserData,
     },
     magicsock::{self, Handle, NodeIdMappedAddr, OwnAddressSnafu},
     metrics::EndpointMetrics,
-    net_report::Report,
+    net_report::{NatReport, RelayHealth, Report},
     tls, RelayProtocol,
 };
 
@@ -106,8 +106,9 @@ pub struct Builder {
     alpn_protocols: Vec<Vec<u8>>,
     transport_config: quinn::TransportConfig,
     keylog: bool,
-    discovery: Vec<Box<dyn DynIntoDiscovery>>,
+    discovery: Vec<(Box<dyn DynIntoDiscovery>, DiscoveryOptions)>,
     discovery_user_data: Option<UserData>,
+    discovery_attributes: Attributes,
     proxy_url: Option<Url>,
     /// List of known nodes. See [`Builder::known_nodes`].
     node_map: Option<Vec<NodeAddr>>,
@@ -134,6 +135,7 @@ impl Default for Builder {
             keylog: Default::default(),
             discovery: Default::default(),
             discovery_user_data: Default::default(),
+            discovery_attributes: Default::default(),
             proxy_url: None,
             node_map: None,
             #[cfg(not(wasm_browser))]
@@ -170,22 +172,17 @@ impl Builder {
         #[cfg(not(wasm_browser))]
         let dns_resolver = self.dns_resolver.unwrap_or_default();
 
-        let discovery: Option<Box<dyn Discovery>> = {
+        let discovery = {
             let context = DiscoveryContext {
                 secret_key: &secret_key,
                 #[cfg(not(wasm_browser))]
                 dns_resolver: &dns_resolver,
             };
-            let discovery = self
-                .discovery
-                .into_iter()
-                .map(|builder| builder.into_discovery(&context))
-                .collect::<Result<Vec<_>, IntoDiscoveryError>>()?;
-            match discovery.len() {
-                0 => None,
-                1 => Some(discovery.into_iter().next().expect("checked length")),
-                _ => Some(Box::new(ConcurrentDiscovery::from_services(discovery))),
+            let discovery = ConcurrentDiscovery::empty();
+            for (builder, options) in self.discovery {
+                discovery.add_boxed(builder.into_discovery(&context)?, options);
             }
+            discovery
         };
 
         let metrics = EndpointMetrics::default();
@@ -199,6 +196,7 @@ impl Builder {
             node_map: self.node_map,
             discovery,
             discovery_user_data: self.discovery_user_data,
+            discovery_attributes: self.discovery_attributes,
             proxy_url: self.proxy_url,
             #[cfg(not(wasm_browser))]
             dns_resolver,
@@ -314,7 +312,8 @@ impl Builder {
     /// See the documentation of the [`Discovery`] trait for details.
     pub fn discovery(mut self, discovery: impl IntoDiscovery) -> Self {
         self.discovery.clear();
-        self.discovery.push(Box::new(discovery));
+        self.discovery
+            .push((Box::new(discovery), DiscoveryOptions::default()));
         self
     }
 
@@ -335,7 +334,23 @@ impl Builder {
     ///
     /// See the documentation of the [`Discovery`] trait for details.
     pub fn add_discovery(mut self, discovery: impl IntoDiscovery) -> Self {
-        self.discovery.push(Box::new(discovery));
+        self.discovery
+            .push((Box::new(discovery), DiscoveryOptions::default()));
+        self
+    }
+
+    /// Adds a discovery mechanism for this endpoint with the given [`DiscoveryOptions`].
+    ///
+    /// The options allow to set the priority of the discovery mechanism relative to the
+    /// other mechanisms and a timeout for its lookups, see [`DiscoveryOptions`].
+    ///
+    /// See [`Builder::add_discovery`] for details.
+    pub fn add_discovery_with_options(
+        mut self,
+        discovery: impl IntoDiscovery,
+        options: DiscoveryOptions,
+    ) -> Self {
+        self.discovery.push((Box::new(discovery), options));
         self
     }
 
@@ -406,6 +421,27 @@ impl Builder {
         self
     }
 
+    /// Sets the initial application-defined attributes to be published in discovery
+    /// services for this node.
+    ///
+    /// The [`Attributes`] are published together with the node's addresses and relay URL,
+    /// and are available from the [`DiscoveryItem`]s when other nodes discover this node.
+    /// This allows applications to filter which discovered nodes to dial.
+    ///
+    /// [`MdnsDiscovery`] always publishes the attributes.  The pkarr publishers only do so
+    /// when enabled with [`PkarrPublisherBuilder::include_attributes`], or the same option
+    /// of the DHT discovery builder: iroh versions from before attributes were added fail
+    /// to parse pkarr and DNS records containing them, so nodes running those versions can
+    /// no longer resolve this node.  Only enable this once all nodes which need to resolve
+    /// this node understand attributes.
+    ///
+    /// [`MdnsDiscovery`]: crate::discovery::mdns::MdnsDiscovery
+    /// [`PkarrPublisherBuilder::include_attributes`]: crate::discovery::pkarr::PkarrPublisherBuilder::include_attributes
+    pub fn attributes_for_discovery(mut self, attributes: Attributes) -> Self {
+        self.discovery_attributes = attributes;
+        self
+    }
+
     /// Optionally set a list of known nodes.
     pub fn known_nodes(mut self, nodes: Vec<NodeAddr>) -> Self {
         self.node_map = Some(nodes);
@@ -546,6 +582,8 @@ pub struct Endpoint {
     rtt_actor: Arc<rtt_actor::RttHandle>,
     /// Configuration structs for quinn, holds the transport config, certificate setup, secret key etc.
     static_config: Arc<StaticConfig>,
+    /// The established connections, by remote node.
+    connections: Arc<OpenConnections>,
 }
 
 #[allow(missing_docs)]
@@ -588,6 +626,25 @@ pub enum ConnectError {
     },
 }
 
+/// Error returned by [`Endpoint::connect_addr`].
+#[allow(missing_docs)]
+#[common_fields({
+    backtrace: Option<snafu::Backtrace>,
+    #[snafu(implicit)]
+    span_trace: n0_snafu::SpanTrace,
+})]
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum ConnectAddrError {
+    #[snafu(display("There already are open connections to node {node_id}"))]
+    OpenConnections { node_id: NodeId },
+    #[snafu(transparent)]
+    Connect {
+        #[snafu(source(from(ConnectError, Box::new)))]
+        source: Box<ConnectError>,
+    },
+}
+
 #[allow(missing_docs)]
 #[common_fields({
     backtrace: Option<snafu::Backtrace>,
@@ -655,6 +712,7 @@ impl Endpoint {
             msock,
             rtt_actor: Arc::new(rtt_actor::RttHandle::new(metrics)),
             static_config: Arc::new(static_config),
+            connections: Default::default(),
         };
         Ok(ep)
     }
@@ -713,6 +771,52 @@ impl Endpoint {
         Ok(conn)
     }
 
+    /// Connects to a remote [`Endpoint`] using only the given path.
+    ///
+    /// Unlike [`Endpoint::connect`], this only uses the given direct address or
+    /// [`RelayUrl`]: paths already known for the node are not tried and [`Discovery`] is not
+    /// used.  This is useful in controlled environments and tests, or when the remote node
+    /// is known to have moved and its known addresses are stale.
+    ///
+    /// Paths are shared by all connections to a node, so this fails with
+    /// [`ConnectAddrError::OpenConnections`] if there already are connections to the node.
+    /// If the connection attempt fails, the previously known paths of the node are restored.
+    /// Once connected, the path is used by later connections to the node as well.
+    pub async fn connect_addr(
+        &self,
+        node_id: NodeId,
+        addr: impl Into<ConnectAddr>,
+        alpn: &[u8],
+    ) -> Result<Connection, ConnectAddrError> {
+        ensure!(
+            !self.connections.contains(&node_id),
+            OpenConnectionsSnafu { node_id }
+        );
+        let node_addr = match addr.into() {
+            ConnectAddr::Direct(addr) => NodeAddr::from_parts(node_id, None, [addr]),
+            ConnectAddr::Relay(url) => NodeAddr::from_parts(node_id, Some(url), []),
+        };
+        let previous = self.remote_info(node_id).map(NodeAddr::from);
+        let options = ConnectOptions {
+            replace_addrs: true,
+            ..ConnectOptions::new().without_discovery()
+        };
+        let res = async {
+            let conn = self
+                .connect_with_opts(node_addr, alpn, options)
+                .await?
+                .await?;
+            Ok::<_, ConnectError>(conn)
+        }
+        .await;
+        if res.is_err() {
+            if let Some(previous) = previous.filter(|addr| !addr.is_empty()) {
+                self.msock.replace_node_addr(previous, Source::App).ok();
+            }
+        }
+        Ok(res?)
+    }
+
     /// Starts a connection attempt with a remote [`Endpoint`].
     ///
     /// Like [`Endpoint::connect`] (see also its docs for general details), but allows for a more
@@ -727,6 +831,9 @@ impl Endpoint {
     ///    **Note:** Please be aware that changing transport config settings may have adverse effects on
     ///    establishing and maintaining direct connections.  Carefully test settings you use and
     ///    consider this currently as still rather experimental.
+    /// 3. Discovery can be disabled for this connection attempt using
+    ///    [`ConnectOptions::without_discovery`], in which case only the addressing
+    ///    information from the [`NodeAddr`] and previously known paths are used.
     #[instrument(name = "connect", skip_all, fields(
         me = self.node_id().fmt_short(),
         remote = tracing::field::Empty,
@@ -744,7 +851,10 @@ impl Endpoint {
         // Connecting to ourselves is not supported.
         ensure!(node_addr.node_id != self.node_id(), SelfConnectSnafu);
 
-        if !node_addr.is_empty() {
+        if options.replace_addrs {
+            self.msock
+                .replace_node_addr(node_addr.clone(), magicsock::Source::App)?;
+        } else if !node_addr.is_empty() {
             self.add_node_addr(node_addr.clone())?;
         }
         let node_id = node_addr.node_id;
@@ -756,7 +866,7 @@ impl Endpoint {
         // verified address information for this node.  Dropping the discovery cancels any
         // still running task.
         let (mapped_addr, _discovery_drop_guard) = self
-            .get_mapping_addr_and_maybe_start_discovery(node_addr)
+            .get_mapping_addr_and_maybe_start_discovery(node_addr, !options.without_discovery)
             .await
             .context(NoAddressSnafu)?;
 
@@ -972,6 +1082,11 @@ impl Endpoint {
     /// This is the server other iroh nodes can use to reliably establish a connection
     /// to this node.
     ///
+    /// The home relay is re-evaluated on every net report.  It moves to another server
+    /// when the current one becomes unreachable, or when another server has a
+    /// significantly lower latency.  The watcher is updated each time this happens, so it
+    /// can be used to learn about home relay changes.
+    ///
     /// The watcher stores `None` if we are not connected to any Relay server.
     ///
     /// Note that this will store `None` right after the [`Endpoint`] is created since it takes
@@ -1063,6 +1178,32 @@ impl Endpoint {
         self.msock.net_report()
     }
 
+    /// Returns a classification of the NAT this endpoint is behind.
+    ///
+    /// This is derived from the most recent net-report and allows predicting whether direct
+    /// connections to other nodes are likely, e.g. to tell users that their connections
+    /// will be relayed.  Returns `None` if no net-report has completed yet.
+    ///
+    /// See [`NatReport`] for details.
+    pub fn nat_report(&self) -> Option<NatReport> {
+        self.msock
+            .net_report()
+            .get()
+            .ok()
+            .flatten()
+            .map(|report| report.nat_report())
+    }
+
+    /// Returns health information about the relay servers.
+    ///
+    /// For each relay server this contains the recent latencies measured by the
+    /// net-reports, when the connection to it last failed and how often it was chosen as
+    /// the home relay.  This allows rendering a relay health view or spotting degraded
+    /// relay servers.  Relay servers which were never measured are not included.
+    pub fn relay_health(&self) -> BTreeMap<RelayUrl, RelayHealth> {
+        self.msock.relay_health()
+    }
+
     /// Returns the local socket addresses on which the underlying sockets are bound.
     ///
     /// The [`Endpoint`] always binds on an IPv4 address and also tries to bind on an IPv6
@@ -1131,6 +1272,28 @@ impl Endpoint {
     /// [`MdnsDiscovery`]: crate::discovery::mdns::MdnsDiscovery
     /// [`StaticProvider`]: crate::discovery::static_provider::StaticProvider
     pub fn discovery_stream(&self) -> impl Stream<Item = Result<DiscoveryItem, Lagged>> {
+        self.msock
+            .discovery_subscribers()
+            .subscribe()
+            .filter_map(|event| match event {
+                Ok(DiscoveryEvent::Discovered(item)) => Some(Ok(item)),
+                Ok(DiscoveryEvent::Expired { .. }) => None,
+                Err(lagged) => Some(Err(lagged)),
+            })
+    }
+
+    /// Returns a stream of all discovery events of the endpoint's discovery services.
+    ///
+    /// In addition to the nodes yielded by [`Endpoint::discovery_stream`], this also yields
+    /// [`DiscoveryEvent::Expired`] when a passively discovered node is no longer available,
+    /// e.g. because its [`MdnsDiscovery`] announcement expired.  This allows applications to
+    /// maintain a list of nearby peers from ambient discovery.
+    ///
+    /// The stream should be processed in a loop. If the stream is not processed fast enough,
+    /// [`Lagged`] may be yielded, indicating that events were missed.
+    ///
+    /// [`MdnsDiscovery`]: crate::discovery::mdns::MdnsDiscovery
+    pub fn discovery_events(&self) -> impl Stream<Item = Result<DiscoveryEvent, Lagged>> {
         self.msock.discovery_subscribers().subscribe()
     }
 
@@ -1180,6 +1343,39 @@ impl Endpoint {
         self.msock.discovery()
     }
 
+    /// Adds a discovery mechanism to the running endpoint.
+    ///
+    /// The discovery mechanism is built using the endpoint's [`DiscoveryContext`] and is
+    /// used by all lookups started after this call.  If the endpoint already knows its
+    /// addressing information, it is published to the new mechanism right away, also when
+    /// no discovery mechanism was configured before.
+    ///
+    /// Events from [`Discovery::subscribe_events`] are only received from the mechanisms
+    /// configured when the endpoint was created.
+    ///
+    /// See [`Builder::add_discovery_with_options`].
+    pub fn add_discovery(
+        &self,
+        discovery: impl IntoDiscovery,
+        options: DiscoveryOptions,
+    ) -> Result<(), IntoDiscoveryError> {
+        let context = DiscoveryContext {
+            secret_key: self.secret_key(),
+            #[cfg(not(wasm_browser))]
+            dns_resolver: self.dns_resolver(),
+        };
+        let discovery = discovery.into_discovery(&context)?;
+        self.msock.add_discovery(Box::new(discovery), options);
+        Ok(())
+    }
+
+    /// Removes all discovery mechanisms from the running endpoint.
+    ///
+    /// Lookups which are already running are not affected.
+    pub fn clear_discovery(&self) {
+        self.msock.clear_discovery();
+    }
+
     /// Returns metrics collected for this endpoint.
     ///
     /// The endpoint internally collects various metrics about its operation.
@@ -1328,6 +1524,33 @@ impl Endpoint {
         self.msock.set_user_data_for_discovery(user_data);
     }
 
+    /// Sets the application-defined attributes to be published in discovery services for
+    /// this node.
+    ///
+    /// If the attributes differ from the previous ones, the endpoint will republish its node
+    /// info to the configured discovery services.
+    ///
+    /// See also [`Builder::attributes_for_discovery`] for setting an initial value when
+    /// building the endpoint.
+    pub fn set_attributes_for_discovery(&self, attributes: Attributes) {
+        self.msock.set_attributes_for_discovery(attributes);
+    }
+
+    /// Replaces the relay servers this endpoint uses.
+    ///
+    /// This allows rolling out new relay servers, e.g. after reloading them from a file or
+    /// fetching them from a URL, without restarting the endpoint.  The home relay is
+    /// re-evaluated against the new relay map: if the current home relay is no longer
+    /// included, or a new relay server has a considerably lower latency, the endpoint moves
+    /// to a new home relay.  Watch [`Endpoint::home_relay`] to observe this.
+    ///
+    /// Setting an empty relay map stops the selection of a new home relay, but does not close
+    /// existing relay connections.  Use [`RelayMode::Disabled`] when building the endpoint
+    /// to not use relay servers at all.
+    pub async fn set_relay_map(&self, relay_map: RelayMap) {
+        self.msock.set_relay_map(relay_map).await;
+    }
+
     // # Methods for terminating the endpoint.
 
     /// Closes the QUIC endpoint and the magic socket.
@@ -1382,7 +1605,7 @@ impl Endpoint {
     /// services if discovery is enabled on this magic endpoint.
     ///
     /// This will launch discovery in all cases except if:
-    /// 1) we do not have discovery enabled
+    /// 1) we do not have discovery enabled, or `use_discovery` is `false`
     /// 2) we have discovery enabled, but already have at least one verified, unexpired
     ///    addresses for this `node_id`
     ///
@@ -1394,6 +1617,7 @@ impl Endpoint {
     async fn get_mapping_addr_and_maybe_start_discovery(
         &self,
         node_addr: NodeAddr,
+        use_discovery: bool,
     ) -> Result<(NodeIdMappedAddr, Option<DiscoveryTask>), GetMappingAddressError> {
         let node_id = node_addr.node_id;
 
@@ -1405,6 +1629,7 @@ impl Endpoint {
             None
         };
         match addr {
+            Some(addr) if !use_discovery => Ok((addr, None)),
             Some(addr) => {
                 // We have some way of dialing this node, but that doesn't actually mean
                 // we can actually connect to any of these addresses.
@@ -1420,6 +1645,7 @@ impl Endpoint {
                 Ok((addr, discovery))
             }
 
+            None if !use_discovery => Err(get_mapping_address_error::NoAddressSnafu.build()),
             None => {
                 // We have no known addresses or relay URLs for this node.
                 // So, we start a discovery task and wait for the first result to arrive, and
@@ -1455,11 +1681,24 @@ impl Endpoint {
     }
 }
 
+/// The path to connect to a node on with [`Endpoint::connect_addr`].
+#[derive(Debug, Clone, PartialEq, Eq, derive_more::From)]
+pub enum ConnectAddr {
+    /// Connect directly to this address.
+    Direct(SocketAddr),
+    /// Connect via this relay server.
+    Relay(RelayUrl),
+}
+
 /// Options for the [`Endpoint::connect_with_opts`] function.
 #[derive(Default, Debug, Clone)]
 pub struct ConnectOptions {
     transport_config: Option<Arc<TransportConfig>>,
     additional_alpns: Vec<Vec<u8>>,
+    without_discovery: bool,
+    /// Replace the known paths of the node instead of adding to them, see
+    /// [`Endpoint::connect_addr`].
+    replace_addrs: bool,
 }
 
 impl ConnectOptions {
@@ -1501,6 +1740,20 @@ impl ConnectOptions {
         self.additional_alpns = alpns;
         self
     }
+
+    /// Disables the [`Discovery`] services for this connection attempt.
+    ///
+    /// The connection will only use the direct addresses and [`RelayUrl`] of the
+    /// [`NodeAddr`] passed to [`Endpoint::connect_with_opts`], together with any paths
+    /// already known to the endpoint.  If there are none, the connection attempt fails
+    /// immediately with [`ConnectWithOptsError::NoAddress`] instead of waiting for
+    /// discovery.
+    ///
+    /// To not use the already known paths either, see [`Endpoint::connect_addr`].
+    pub fn without_discovery(mut self) -> Self {
+        self.without_discovery = true;
+        self
+    }
 }
 
 /// Future produced by [`Endpoint::accept`].
@@ -1834,6 +2087,49 @@ pub struct Connection {
     inner: quinn::Connection,
 }
 
+/// Estimates of the transport about the current path of a [`Connection`].
+///
+/// Returned by [`Connection::transport_stats`].  These are the congestion controller's
+/// estimates: they react to changes on the path with a delay of a few round-trips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TransportStats {
+    /// Current best estimate of the round-trip-time.
+    pub rtt: Duration,
+    /// Current congestion window in bytes.
+    pub cwnd: u64,
+    /// Rate at which the congestion controller currently allows sending, in bytes per second.
+    ///
+    /// This is one congestion window per round-trip-time.  It is an upper bound on what the
+    /// sender may put on the wire, not a measurement of the path's bandwidth: the window
+    /// keeps growing while the application sends less than it allows.
+    ///
+    /// `None` if the round-trip-time is zero.
+    pub send_rate: Option<u64>,
+    /// Number of packets lost on the path.
+    pub lost_packets: u64,
+    /// Number of packets sent on the path.
+    pub sent_packets: u64,
+    /// Largest UDP payload size the path currently supports.
+    pub current_mtu: u16,
+}
+
+impl TransportStats {
+    fn from_path_stats(path: &PathStats) -> Self {
+        let rtt_micros = path.rtt.as_micros();
+        let send_rate = (rtt_micros > 0)
+            .then(|| u64::try_from(path.cwnd as u128 * 1_000_000 / rtt_micros).unwrap_or(u64::MAX));
+        Self {
+            rtt: path.rtt,
+            cwnd: path.cwnd,
+            send_rate,
+            lost_packets: path.lost_packets,
+            sent_packets: path.sent_packets,
+            current_mtu: path.current_mtu,
+        }
+    }
+}
+
 #[allow(missing_docs)]
 #[derive(Debug, Snafu)]
 #[snafu(display("Protocol error: no remote id available"))]
@@ -2007,6 +2303,14 @@ impl Connection {
         self.inner.stats()
     }
 
+    /// Returns the transport's current estimates of the connection's path.
+    ///
+    /// This allows applications to adapt to the available bandwidth, e.g. by picking the
+    /// quality of a media stream.  See [`TransportStats`] for details.
+    pub fn transport_stats(&self) -> TransportStats {
+        TransportStats::from_path_stats(&self.inner.stats().path)
+    }
+
     /// Current state of the congestion control algorithm, for debugging purposes.
     #[inline]
     pub fn congestion_state(&self) -> Box<dyn quinn_proto::congestion::Controller> {
@@ -2138,7 +2442,7 @@ impl Connection {
     }
 }
 
-/// Try send a message to the rtt-actor.
+/// Tracks the connection and tries to send a message to the rtt-actor.
 ///
 /// If we can't notify the actor that will impact performance a little, but we can still
 /// function.
@@ -2148,6 +2452,7 @@ fn try_send_rtt_msg(conn: &Connection, magic_ep: &Endpoint, remote_node_id: Opti
         warn!(?conn, "failed to get remote node id");
         return;
     };
+    magic_ep.connections.insert(node_id, conn);
     let Some(conn_type_changes) = magic_ep.conn_type(node_id) else {
         warn!(?conn, "failed to create conn_type stream");
         return;
@@ -2162,6 +2467,34 @@ fn try_send_rtt_msg(conn: &Connection, magic_ep: &Endpoint, remote_node_id: Opti
     }
 }
 
+/// The established connections of an [`Endpoint`], by remote node.
+///
+/// Only weak handles are kept, closed connections are pruned when a connection is added.
+#[derive(Debug, Default)]
+struct OpenConnections(std::sync::Mutex<BTreeMap<NodeId, Vec<WeakConnectionHandle>>>);
+
+impl OpenConnections {
+    fn insert(&self, node_id: NodeId, conn: &Connection) {
+        let mut conns = self.0.lock().expect("poisoned");
+        conns.retain(|_, handles| {
+            handles.retain(|handle| handle.is_alive());
+            !handles.is_empty()
+        });
+        conns
+            .entry(node_id)
+            .or_default()
+            .push(conn.inner.weak_handle());
+    }
+
+    /// Returns whether there are open connections to `node_id`.
+    fn contains(&self, node_id: &NodeId) -> bool {
+        let conns = self.0.lock().expect("poisoned");
+        conns
+            .get(node_id)
+            .is_some_and(|handles| handles.iter().any(|handle| handle.is_alive()))
+    }
+}
+
 /// Read a proxy url from the environment, in this order
 ///
 /// - `HTTP_PROXY`
@@ -2261,11 +2594,12 @@ fn is_cgi() -> bool {
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::BTreeSet,
         net::SocketAddr,
         time::{Duration, Instant},
     };
 
-    use iroh_base::{NodeAddr, NodeId, SecretKey};
+    use iroh_base::{NodeAddr, NodeId, RelayUrl, SecretKey};
     use iroh_relay::http::Protocol;
     use n0_future::{task::AbortOnDropHandle, StreamExt};
     use n0_snafu::{Error, Result, ResultExt};
@@ -2277,7 +2611,11 @@ mod tests {
 
     use super::Endpoint;
     use crate::{
-        endpoint::{ConnectOptions, Connection, ConnectionType, RemoteInfo},
+        discovery::static_provider::StaticProvider,
+        endpoint::{
+            ConnectAddrError, ConnectOptions, ConnectWithOptsError, Connection, ConnectionType,
+            PathStats, RemoteInfo, TransportStats,
+        },
         test_utils::{run_relay_server, run_relay_server_with},
         RelayMode,
     };
@@ -2305,6 +2643,110 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_connect_without_discovery() -> Result {
+        let server = Endpoint::builder()
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let server_addr = server.node_addr().initialized().await?;
+
+        let discovery = StaticProvider::new();
+        discovery.add_node_info(server_addr.clone());
+        let client = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .discovery(discovery)
+            .bind()
+            .await?;
+
+        // The discovery knows the server, but is not used.
+        let res = client
+            .connect_with_opts(
+                server.node_id(),
+                TEST_ALPN,
+                ConnectOptions::new().without_discovery(),
+            )
+            .await;
+        assert!(matches!(res, Err(ConnectWithOptsError::NoAddress { .. })));
+
+        // The explicitly provided address is used.
+        let server_task = tokio::spawn(async move {
+            let conn = server.accept().await.e()?.await.e()?;
+            conn.closed().await;
+            Ok::<_, Error>(())
+        });
+        let conn = client
+            .connect_with_opts(
+                server_addr,
+                TEST_ALPN,
+                ConnectOptions::new().without_discovery(),
+            )
+            .await?
+            .await
+            .e()?;
+        conn.close(0u32.into(), b"done");
+        server_task.await.e()??;
+        client.close().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_connect_addr_ignores_known_paths() -> Result {
+        let server = Endpoint::builder()
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let server_addr = server.node_addr().initialized().await?;
+        let port = server.bound_sockets()[0].port();
+        let addr = SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, port));
+        let client = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+
+        // A stale address of the server, "240.0.0.0/4" is reserved and unreachable.
+        let stale_addr: SocketAddr = "240.0.0.1:1234".parse().unwrap();
+        let relay_url: RelayUrl = "https://relay.example".parse().unwrap();
+        client.add_node_addr(NodeAddr::from_parts(
+            server.node_id(),
+            Some(relay_url),
+            [stale_addr],
+        ))?;
+
+        let server_task = tokio::spawn(async move {
+            let conn = server.accept().await.e()?.await.e()?;
+            conn.closed().await;
+            Ok::<_, Error>(())
+        });
+
+        let conn = client
+            .connect_addr(server_addr.node_id, addr, TEST_ALPN)
+            .await?;
+
+        let info = client
+            .remote_info(conn.remote_node_id()?)
+            .context("no remote info")?;
+        assert_eq!(info.relay_url, None);
+        let addrs: BTreeSet<SocketAddr> = info.addrs.iter().map(|info| info.addr).collect();
+        assert!(addrs.contains(&addr));
+        assert!(!addrs.contains(&stale_addr));
+
+        // The paths of nodes with open connections are not replaced.
+        let res = client
+            .connect_addr(server_addr.node_id, stale_addr, TEST_ALPN)
+            .await;
+        assert!(matches!(res, Err(ConnectAddrError::OpenConnections { .. })));
+
+        conn.close(0u32.into(), b"done");
+        server_task.await.e()??;
+        client.close().await;
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn endpoint_connect_close() -> Result {
@@ -2561,6 +3003,44 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn endpoint_set_relay_map() -> Result {
+        let (relay_map_a, relay_url_a, _guard_a) = run_relay_server().await?;
+        let (relay_map_b, relay_url_b, _guard_b) = run_relay_server().await?;
+        let ep = Endpoint::builder()
+            .insecure_skip_relay_cert_verify(true)
+            .relay_mode(RelayMode::Custom(relay_map_a))
+            .bind()
+            .await?;
+        let mut home_relay = ep.home_relay().stream();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(urls) = home_relay.next().await {
+                if urls.contains(&relay_url_a) {
+                    break;
+                }
+            }
+        })
+        .await
+        .context("timeout waiting for first home relay")?;
+
+        // Relay a is no longer in the map, so b must become the home relay.
+        ep.set_relay_map(relay_map_b).await;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(urls) = home_relay.next().await {
+                if urls.contains(&relay_url_b) {
+                    break;
+                }
+            }
+        })
+        .await
+        .context("timeout waiting for new home relay")?;
+        assert_eq!(ep.home_relay().get()?, vec![relay_url_b]);
+
+        ep.close().await;
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn endpoint_send_relay_websockets() -> Result {
@@ -3034,6 +3514,58 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn connection_transport_stats() -> Result {
+        let client = Endpoint::builder().bind().await?;
+        let server = Endpoint::builder()
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind()
+            .await?;
+        let server_addr = server.node_addr().initialized().await?;
+        let server_task = tokio::spawn(async move {
+            let incoming = server.accept().await.e()?;
+            let conn = incoming.await.e()?;
+            let (mut send, mut recv) = conn.accept_bi().await.e()?;
+            let msg = recv.read_to_end(100_000).await.e()?;
+            send.write_all(&msg).await.e()?;
+            send.finish().e()?;
+            conn.closed().await;
+            Ok::<_, Error>(())
+        });
+
+        let conn = client.connect(server_addr, TEST_ALPN).await?;
+        let (mut send, mut recv) = conn.open_bi().await.e()?;
+        send.write_all(&[0u8; 50_000]).await.e()?;
+        send.finish().e()?;
+        recv.read_to_end(100_000).await.e()?;
+
+        let stats = conn.transport_stats();
+        assert!(stats.rtt > Duration::ZERO);
+        assert!(stats.cwnd > 0);
+        assert!(stats.send_rate.is_some_and(|rate| rate > 0));
+        assert!(stats.sent_packets > 0);
+        assert!(stats.current_mtu > 0);
+
+        conn.close(0u32.into(), b"done");
+        client.close().await;
+        server_task.await.e()??;
+        Ok(())
+    }
+
+    #[test]
+    fn transport_stats_send_rate() {
+        let mut path = PathStats::default();
+        path.cwnd = 12_000;
+        path.rtt = Duration::from_millis(100);
+        assert_eq!(
+            TransportStats::from_path_stats(&path).send_rate,
+            Some(120_000)
+        );
+        path.rtt = Duration::ZERO;
+        assert_eq!(TransportStats::from_path_stats(&path).send_rate, None);
+    }
+
     #[cfg(feature = "metrics")]
     #[tokio::test]
     #[traced_test]
@@ -3209,4 +3741,26 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_relay_health() -> Result {
+        let (relay_map, relay_url, _guard) = run_relay_server().await?;
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Custom(relay_map))
+            .insecure_skip_relay_cert_verify(true)
+            .bind()
+            .await?;
+        ep.home_relay().initialized().await?;
+
+        let health = ep.relay_health();
+        let relay = health.get(&relay_url).context("no health for relay")?;
+        assert!(relay.is_home);
+        assert_eq!(relay.home_count, 1);
+        assert!(relay.latest_latency().is_some());
+        assert!(relay.last_failure.is_none());
+
+        ep.close().await;
+        Ok(())
+    }
 }
@@ -250,7 +250,7 @@
 #![deny(missing_docs, rustdoc::broken_intra_doc_links)]
 #![cfg_attr(wasm_browser, allow(unused))]
 #![cfg_attr(not(test), deny(clippy::unwrap_used))]
-#![cfg_attr(iroh_docsrs, feature(doc_auto_cfg))]
+#![cfg_attr(iroh_docsrs, feature(doc_auto_cfg, doc_cfg))]
 
 mod disco;
 mod key;
@@ -269,6 +269,9 @@ pub mod endpoint;
 pub mod metrics;
 pub mod net_report;
 pub mod protocol;
+#[cfg(all(not(wasm_browser), any(test, feature = "relay-server")))]
+#[cfg_attr(iroh_docsrs, doc(cfg(feature = "relay-server")))]
+pub mod relay_server;
 
 pub use endpoint::{Endpoint, RelayMode};
 pub use iroh_base::{
@@ -71,10 +71,15 @@ use crate::net_report::{IpMappedAddr, QuicConfig};
 use crate::{
     defaults::timeouts::NET_REPORT_TIMEOUT,
     disco::{self, SendAddr},
-    discovery::{Discovery, DiscoveryItem, DiscoverySubscribers, NodeData, UserData},
+    discovery::{
+        Attributes, ConcurrentDiscovery, Discovery, DiscoveryEvent, DiscoveryOptions,
+        DiscoverySubscribers, NodeData, UserData,
+    },
     key::{public_ed_box, secret_ed_box, DecryptionError, SharedSecret},
     metrics::EndpointMetrics,
-    net_report::{self, IfStateDetails, IpMappedAddresses, Report},
+    net_report::{
+        self, IfStateDetails, IpMappedAddresses, RelayHealth, RelayHealthTracker, Report,
+    },
 };
 
 mod metrics;
@@ -119,12 +124,15 @@ pub(crate) struct Options {
     /// An optional [`NodeMap`], to restore information about nodes.
     pub(crate) node_map: Option<Vec<NodeAddr>>,
 
-    /// Optional node discovery mechanism.
-    pub(crate) discovery: Option<Box<dyn Discovery>>,
+    /// The node discovery services, may be empty.
+    pub(crate) discovery: ConcurrentDiscovery,
 
     /// Optional user-defined discovery data.
     pub(crate) discovery_user_data: Option<UserData>,
 
+    /// Application-defined attributes published to discovery.
+    pub(crate) discovery_attributes: Attributes,
+
     /// A DNS resolver to use for resolving relay URLs.
     ///
     /// You can use [`crate::dns::DnsResolver::new`] for a resolver
@@ -198,6 +206,8 @@ pub(crate) struct MagicSock {
     direct_addrs: DiscoveredDirectAddrs,
     /// Our latest net-report
     net_report: Watchable<(Option<Report>, UpdateReason)>,
+    /// Health of the relay servers, collected from the net-reports.
+    relay_health: Mutex<RelayHealthTracker>,
     /// If the last net_report report, reports IPv6 to be available.
     ipv6_reported: Arc<AtomicBool>,
     /// Tracks the networkmap node entity for each node discovery key.
@@ -217,10 +227,16 @@ pub(crate) struct MagicSock {
     disco: DiscoState,
 
     // - Discovery
-    /// Optional discovery service
-    discovery: Option<Box<dyn Discovery>>,
+    /// The discovery services, may be empty
+    discovery: ConcurrentDiscovery,
     /// Optional user-defined discover data.
     discovery_user_data: RwLock<Option<UserData>>,
+    /// Application-defined attributes published to discovery.
+    discovery_attributes: RwLock<Attributes>,
+    /// The data last published to discovery.
+    ///
+    /// Held while publishing, so that services added at runtime can not miss an update.
+    published_data: Mutex<Option<NodeData>>,
     /// Broadcast channel for listening to discovery updates.
     discovery_subscribers: DiscoverySubscribers,
 
@@ -346,6 +362,11 @@ impl MagicSock {
             .expect("disconnected")
     }
 
+    /// Returns the health information of all relay servers.
+    pub(crate) fn relay_health(&self) -> BTreeMap<RelayUrl, RelayHealth> {
+        self.relay_health.lock().expect("poisoned").get()
+    }
+
     /// Watch for changes to the home relay.
     ///
     /// Note that this can be used to wait for the initial home relay to be known using
@@ -391,6 +412,33 @@ impl MagicSock {
         &self,
         mut addr: NodeAddr,
         source: node_map::Source,
+    ) -> Result<(), AddNodeAddrError> {
+        self.prune_own_addrs(&mut addr, &source)?;
+        self.node_map
+            .add_node_addr(addr, source, &self.metrics.magicsock);
+        Ok(())
+    }
+
+    /// Replace the addresses for a node in the magic socket's addressbook.
+    ///
+    /// Any direct addresses or relay URL of the node which are not in `addr` are removed.
+    #[instrument(skip_all)]
+    pub(crate) fn replace_node_addr(
+        &self,
+        mut addr: NodeAddr,
+        source: node_map::Source,
+    ) -> Result<(), AddNodeAddrError> {
+        self.prune_own_addrs(&mut addr, &source)?;
+        self.node_map
+            .replace_node_addr(addr, source, &self.metrics.magicsock);
+        Ok(())
+    }
+
+    /// Removes our own direct addresses from `addr`, erroring if nothing is left.
+    fn prune_own_addrs(
+        &self,
+        addr: &mut NodeAddr,
+        source: &node_map::Source,
     ) -> Result<(), AddNodeAddrError> {
         let mut pruned: usize = 0;
         for my_addr in self.direct_addrs.sockaddrs() {
@@ -400,8 +448,6 @@ impl MagicSock {
             }
         }
         if !addr.is_empty() {
-            self.node_map
-                .add_node_addr(addr, source, &self.metrics.magicsock);
             Ok(())
         } else if pruned != 0 {
             Err(EmptyPrunedSnafu { pruned }.build())
@@ -429,9 +475,27 @@ impl MagicSock {
         &self.dns_resolver
     }
 
-    /// Reference to optional discovery service
+    /// Reference to the discovery service, if any discovery services are configured.
     pub(crate) fn discovery(&self) -> Option<&dyn Discovery> {
-        self.discovery.as_ref().map(Box::as_ref)
+        if self.discovery.is_empty() {
+            None
+        } else {
+            Some(&self.discovery)
+        }
+    }
+
+    /// Adds a discovery service, publishing our current data to it.
+    pub(crate) fn add_discovery(&self, service: Box<dyn Discovery>, options: DiscoveryOptions) {
+        let published = self.published_data.lock().expect("poisoned");
+        if let Some(data) = published.as_ref() {
+            service.publish(data);
+        }
+        self.discovery.add_boxed(service, options);
+    }
+
+    /// Removes all discovery services.
+    pub(crate) fn clear_discovery(&self) {
+        self.discovery.clear();
     }
 
     /// Updates the user-defined discovery data for this node.
@@ -444,6 +508,16 @@ impl MagicSock {
         }
     }
 
+    /// Updates the application-defined discovery attributes for this node.
+    pub(crate) fn set_attributes_for_discovery(&self, attributes: Attributes) {
+        let mut guard = self.discovery_attributes.write().expect("lock poisened");
+        if *guard != attributes {
+            *guard = attributes;
+            drop(guard);
+            self.publish_my_addr();
+        }
+    }
+
     /// Call to notify the system of potential network changes.
     pub(crate) async fn network_change(&self) {
         self.actor_sender
@@ -457,6 +531,14 @@ impl MagicSock {
         &self.discovery_subscribers
     }
 
+    /// Replaces the relay map and re-evaluates the home relay.
+    pub(crate) async fn set_relay_map(&self, relay_map: RelayMap) {
+        self.actor_sender
+            .send(ActorMessage::SetRelayMap(relay_map))
+            .await
+            .ok();
+    }
+
     #[cfg(test)]
     async fn force_network_change(&self, is_major: bool) {
         self.actor_sender
@@ -1025,27 +1107,39 @@ impl MagicSock {
         }
     }
 
-    /// Publishes our address to a discovery service, if configured.
+    /// Publishes our address to the discovery services.
     ///
-    /// Called whenever our addresses or home relay node changes.
+    /// Called whenever our addresses or home relay node changes.  The data is kept even
+    /// if no discovery service is configured, to publish it to services added later.
     fn publish_my_addr(&self) {
-        if let Some(ref discovery) = self.discovery {
-            let relay_url = self.my_relay();
-            let direct_addrs = self.direct_addrs.sockaddrs();
-
-            let user_data = self
-                .discovery_user_data
-                .read()
-                .expect("lock poisened")
-                .clone();
-            if relay_url.is_none() && direct_addrs.is_empty() && user_data.is_none() {
-                // do not bother publishing if we don't have any information
-                return;
-            }
-
-            let data = NodeData::new(relay_url, direct_addrs).with_user_data(user_data);
-            discovery.publish(&data);
+        let relay_url = self.my_relay();
+        let direct_addrs = self.direct_addrs.sockaddrs();
+
+        let user_data = self
+            .discovery_user_data
+            .read()
+            .expect("lock poisened")
+            .clone();
+        let attributes = self
+            .discovery_attributes
+            .read()
+            .expect("lock poisened")
+            .clone();
+        if relay_url.is_none()
+            && direct_addrs.is_empty()
+            && user_data.is_none()
+            && attributes.is_empty()
+        {
+            // do not bother publishing if we don't have any information
+            return;
         }
+
+        let data = NodeData::new(relay_url, direct_addrs)
+            .with_user_data(user_data)
+            .with_attributes(attributes);
+        let mut published = self.published_data.lock().expect("poisoned");
+        self.discovery.publish(&data);
+        *published = Some(data);
     }
 }
 
@@ -1091,6 +1185,8 @@ struct DirectAddrUpdateState {
     /// The prober that discovers local network conditions, including the closest relay relay and NAT mappings.
     net_reporter: Arc<AsyncMutex<net_report::Client>>,
     relay_map: RelayMap,
+    /// Set when the relay map changed, to be passed to the net_reporter on the next run.
+    new_relay_map: Option<RelayMap>,
     run_done: mpsc::Sender<()>,
 }
 
@@ -1104,6 +1200,8 @@ enum UpdateReason {
     PortmapUpdated,
     LinkChangeMajor,
     LinkChangeMinor,
+    HomeRelayUnreachable,
+    RelayMapChanged,
 }
 
 impl UpdateReason {
@@ -1127,10 +1225,17 @@ impl DirectAddrUpdateState {
             net_reporter,
             msock,
             relay_map,
+            new_relay_map: None,
             run_done,
         }
     }
 
+    /// Replaces the relay map, used from the next run onwards.
+    fn set_relay_map(&mut self, relay_map: RelayMap) {
+        self.relay_map = relay_map.clone();
+        self.new_relay_map = Some(relay_map);
+    }
+
     /// Schedules a new run, either starting it immediately if none is running or
     /// scheduling it for later.
     fn schedule_run(&mut self, why: UpdateReason, if_state: IfStateDetails) {
@@ -1166,6 +1271,9 @@ impl DirectAddrUpdateState {
         mut net_reporter: tokio::sync::OwnedMutexGuard<net_report::Client>,
     ) {
         debug!("starting direct addr update ({:?})", why);
+        if let Some(relay_map) = self.new_relay_map.take() {
+            net_reporter.set_relay_map(relay_map);
+        }
         #[cfg(not(wasm_browser))]
         self.port_mapper.procure_mapping();
         // Don't start a net report probe if we know
@@ -1238,6 +1346,7 @@ impl Handle {
             node_map,
             discovery,
             discovery_user_data,
+            discovery_attributes,
             #[cfg(not(wasm_browser))]
             dns_resolver,
             proxy_url,
@@ -1268,9 +1377,11 @@ impl Handle {
 
         let my_relay = Watchable::new(None);
         let ipv6_reported = Arc::new(AtomicBool::new(false));
+        let (home_relay_unreachable_tx, home_relay_unreachable_rx) = mpsc::channel(8);
 
         let relay_transport = RelayTransport::new(RelayActorConfig {
             my_relay: my_relay.clone(),
+            home_relay_unreachable: home_relay_unreachable_tx,
             secret_key: secret_key.clone(),
             #[cfg(not(wasm_browser))]
             dns_resolver: dns_resolver.clone(),
@@ -1305,8 +1416,11 @@ impl Handle {
             ip_mapped_addrs: ip_mapped_addrs.clone(),
             discovery,
             discovery_user_data: RwLock::new(discovery_user_data),
+            discovery_attributes: RwLock::new(discovery_attributes),
+            published_data: Default::default(),
             direct_addrs: Default::default(),
             net_report: Watchable::new((None, UpdateReason::None)),
+            relay_health: Default::default(),
             #[cfg(not(wasm_browser))]
             dns_resolver: dns_resolver.clone(),
             discovery_subscribers: DiscoverySubscribers::new(),
@@ -1402,6 +1516,7 @@ impl Handle {
             direct_addr_done_rx,
             pending_call_me_maybes: Default::default(),
             disco_receiver,
+            home_relay_unreachable_rx,
         };
 
         let actor_token = CancellationToken::new();
@@ -1651,6 +1766,7 @@ enum ActorMessage {
     EndpointPingExpired(usize, stun_rs::TransactionId),
     NetworkChange,
     ScheduleDirectAddrUpdate(UpdateReason, Option<(NodeId, RelayUrl)>),
+    SetRelayMap(RelayMap),
     #[cfg(test)]
     ForceNetworkChange(bool),
 }
@@ -1672,6 +1788,8 @@ struct Actor {
     /// the next endpoint update completes
     pending_call_me_maybes: HashMap<PublicKey, RelayUrl>,
     disco_receiver: mpsc::Receiver<(SendAddr, PublicKey, disco::Message)>,
+    /// Receives the URL of the home relay when it can no longer be reached.
+    home_relay_unreachable_rx: mpsc::Receiver<RelayUrl>,
 }
 
 #[cfg(not(wasm_browser))]
@@ -1747,9 +1865,9 @@ impl Actor {
             .port_mapper
             .watch_external_address();
 
-        let mut discovery_events: BoxStream<DiscoveryItem> = Box::pin(n0_future::stream::empty());
+        let mut discovery_events: BoxStream<DiscoveryEvent> = Box::pin(n0_future::stream::empty());
         if let Some(d) = self.msock.discovery() {
-            if let Some(events) = d.subscribe() {
+            if let Some(events) = d.subscribe_events() {
                 discovery_events = events;
             }
         }
@@ -1795,6 +1913,15 @@ impl Actor {
                     self.msock.metrics.magicsock.actor_tick_re_stun.inc();
                     self.re_stun(UpdateReason::Periodic);
                 }
+                Some(url) = self.home_relay_unreachable_rx.recv() => {
+                    self.msock.relay_health.lock().expect("poisoned").on_failure(&url);
+                    // Only fail over if this is still our home relay, a report may have
+                    // already moved us elsewhere.
+                    if self.msock.my_relay().as_ref() == Some(&url) {
+                        debug!(%url, "home relay unreachable, re-running net_report");
+                        self.re_stun(UpdateReason::HomeRelayUnreachable);
+                    }
+                }
                 new_addr = watcher.updated() => {
                     match new_addr {
                         Ok(addrs) => {
@@ -1884,20 +2011,22 @@ impl Actor {
                 // Even if `discovery_events` yields `None`, it could begin to yield
                 // `Some` again in the future, so we don't want to disable this branch
                 // forever like we do with the other branches that yield `Option`s
-                Some(discovery_item) = discovery_events.next() => {
-                    trace!("tick: discovery event, address discovered: {discovery_item:?}");
-                    let provenance = discovery_item.provenance();
-                    let node_addr = discovery_item.to_node_addr();
-                    if let Err(e) = self.msock.add_node_addr(
-                        node_addr,
-                        Source::Discovery {
-                            name: provenance.to_string()
-                        }) {
+                Some(discovery_event) = discovery_events.next() => {
+                    trace!("tick: discovery event: {discovery_event:?}");
+                    if let DiscoveryEvent::Discovered(ref discovery_item) = discovery_event {
+                        let provenance = discovery_item.provenance();
                         let node_addr = discovery_item.to_node_addr();
-                        warn!(?node_addr, "unable to add discovered node address to the node map: {e:?}");
+                        if let Err(e) = self.msock.add_node_addr(
+                            node_addr,
+                            Source::Discovery {
+                                name: provenance.to_string()
+                            }) {
+                            let node_addr = discovery_item.to_node_addr();
+                            warn!(?node_addr, "unable to add discovered node address to the node map: {e:?}");
+                        }
                     }
-                    // Send the discovery item to the subscribers of the discovery broadcast stream.
-                    self.msock.discovery_subscribers.send(discovery_item);
+                    // Send the discovery event to the subscribers of the discovery broadcast stream.
+                    self.msock.discovery_subscribers.send(discovery_event);
                 }
                 Some((dst, dst_key, msg)) = self.disco_receiver.recv() => {
                     if let Err(err) = self.msock.send_disco_message(&sender, dst.clone(), dst_key, msg).await {
@@ -1957,6 +2086,16 @@ impl Actor {
                 self.direct_addr_update_state
                     .schedule_run(why, state.into());
             }
+            ActorMessage::SetRelayMap(relay_map) => {
+                debug!(relays = relay_map.len(), "relay map changed");
+                self.msock
+                    .relay_health
+                    .lock()
+                    .expect("poisoned")
+                    .retain(&relay_map);
+                self.direct_addr_update_state.set_relay_map(relay_map);
+                self.re_stun(UpdateReason::RelayMapChanged);
+            }
             #[cfg(test)]
             ActorMessage::ForceNetworkChange(is_major) => {
                 self.handle_network_change(is_major).await;
@@ -2126,11 +2265,22 @@ impl Actor {
         if let Some(ref mut r) = report {
             self.msock.ipv6_reported.store(r.udp_v6, Ordering::Relaxed);
             if r.preferred_relay.is_none() {
-                if let Some(my_relay) = self.msock.my_relay() {
+                // Keep the current home relay, unless it was removed from the relay map.
+                if let Some(my_relay) = self
+                    .msock
+                    .my_relay()
+                    .filter(|url| self.direct_addr_update_state.relay_map.contains_node(url))
+                {
                     r.preferred_relay.replace(my_relay);
                 }
             }
 
+            self.msock
+                .relay_health
+                .lock()
+                .expect("poisoned")
+                .on_report(r);
+
             // Notify all transports
             self.network_change_sender.on_network_change(r);
         }
@@ -2451,7 +2601,7 @@ mod tests {
                 relay_map: RelayMap::empty(),
                 relay_protocol: iroh_relay::http::Protocol::default(),
                 node_map: None,
-                discovery: None,
+                discovery: Default::default(),
                 proxy_url: None,
                 dns_resolver: DnsResolver::new(),
                 server_config,
@@ -2460,6 +2610,7 @@ mod tests {
                 #[cfg(any(test, feature = "test-utils"))]
                 path_selection: PathSelection::default(),
                 discovery_user_data: None,
+                discovery_attributes: Default::default(),
                 metrics: Default::default(),
             }
         }
@@ -2984,8 +3135,9 @@ mod tests {
             relay_map: RelayMap::empty(),
             relay_protocol: iroh_relay::http::Protocol::default(),
             node_map: None,
-            discovery: None,
+            discovery: Default::default(),
             discovery_user_data: None,
+            discovery_attributes: Default::default(),
             dns_resolver,
             proxy_url: None,
             server_config,
@@ -151,6 +151,16 @@ impl NodeMap {
             .add_node_addr(node_addr, source, metrics)
     }
 
+    /// Replace the contact information for a node.
+    ///
+    /// Direct addresses and the relay URL which are not part of `node_addr` are removed.
+    pub(super) fn replace_node_addr(&self, node_addr: NodeAddr, source: Source, metrics: &Metrics) {
+        self.inner
+            .lock()
+            .expect("poisoned")
+            .replace_node_addr(node_addr, source, metrics)
+    }
+
     /// Number of nodes currently listed.
     pub(super) fn node_count(&self) -> usize {
         self.inner.lock().expect("poisoned").node_count()
@@ -377,6 +387,37 @@ impl NodeMapInner {
         }
     }
 
+    fn replace_node_addr(&mut self, node_addr: NodeAddr, source: Source, metrics: &Metrics) {
+        let source0 = source.clone();
+        let node_id = node_addr.node_id;
+        let relay_url = node_addr.relay_url.clone();
+        #[cfg(any(test, feature = "test-utils"))]
+        let path_selection = self.path_selection;
+        let node_state = self.get_or_insert_with(NodeStateKey::NodeId(node_id), || Options {
+            node_id,
+            relay_url,
+            active: false,
+            source,
+            #[cfg(any(test, feature = "test-utils"))]
+            path_selection,
+        });
+        let removed = node_state.replace_node_addr(
+            node_addr.relay_url.as_ref(),
+            &node_addr.direct_addresses,
+            source0,
+            metrics,
+        );
+        let id = node_state.id();
+        for ipp in removed {
+            if self.by_ip_port.get(&ipp) == Some(&id) {
+                self.by_ip_port.remove(&ipp);
+            }
+        }
+        for addr in node_addr.direct_addresses() {
+            self.set_node_state_for_ip_port(*addr, id);
+        }
+    }
+
     /// Prunes direct addresses from nodes that claim to share an address we know points to us.
     pub(super) fn on_direct_addr_discovered(&mut self, discovered: BTreeSet<SocketAddr>) {
         for addr in discovered {
@@ -60,6 +60,7 @@ pub enum ClearReason {
     Inactive,
     PongTimeout,
     MatchesOurLocalAddr,
+    Replaced,
 }
 
 impl BestAddr {
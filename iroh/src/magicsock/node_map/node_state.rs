@@ -709,6 +709,38 @@ impl NodeState {
         debug!(new = ?new_addrs , %paths, "added new direct paths for endpoint");
     }
 
+    /// Replaces the paths of this node with the given relay URL and direct addresses.
+    ///
+    /// All other direct addresses are removed and the relay URL is cleared if none is
+    /// given.  Returns the removed direct addresses.
+    pub(super) fn replace_node_addr(
+        &mut self,
+        new_relay_url: Option<&RelayUrl>,
+        new_addrs: &BTreeSet<SocketAddr>,
+        source: super::Source,
+        metrics: &MagicsockMetrics,
+    ) -> Vec<IpPort> {
+        let removed: Vec<IpPort> = self
+            .udp_paths
+            .paths
+            .keys()
+            .filter(|ipp| !new_addrs.contains(&SocketAddr::from(**ipp)))
+            .copied()
+            .collect();
+        for ipp in &removed {
+            self.remove_direct_addr(ipp, ClearReason::Replaced);
+        }
+        if new_relay_url.is_none() && self.relay_url.is_some() {
+            debug!("Removing relay node {:?}", self.relay_url);
+            if self.udp_paths.best_addr.is_empty() {
+                metrics.num_relay_conns_removed.inc();
+            }
+            self.relay_url = None;
+        }
+        self.update_from_node_addr(new_relay_url, new_addrs, source, metrics);
+        removed
+    }
+
     /// Handle a received Disco Ping.
     ///
     /// - Ensures the paths the ping was received on is a known path for this endpoint.
@@ -1413,7 +1445,7 @@ impl RemoteInfo {
                 }
             }
         }
-        sources.sort_by(|a, b| b.1.cmp(&a.1));
+        sources.sort_by_key(|s| std::cmp::Reverse(s.1));
         sources
     }
 }
@@ -1708,8 +1740,8 @@ mod tests {
 
     fn remove_non_deterministic_fields(infos: &mut [RemoteInfo]) {
         for info in infos.iter_mut() {
-            if info.relay_url.is_some() {
-                info.relay_url.as_mut().unwrap().last_alive = None;
+            if let Some(relay_url) = info.relay_url.as_mut() {
+                relay_url.last_alive = None;
             }
         }
     }
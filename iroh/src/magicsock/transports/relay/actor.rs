@@ -101,6 +101,18 @@ const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 /// This value is set to 3 times the QUIC initial Probe Timeout (PTO).
 const UNDELIVERABLE_DATAGRAM_TIMEOUT: Duration = Duration::from_secs(3);
 
+/// Number of consecutive failed connection attempts to the home relay after which it is
+/// considered unreachable.
+///
+/// Once reached the magicsock is notified so it can run a new net_report and fail over to
+/// the next-best relay server instead of waiting for the next periodic report.  It is
+/// notified again after every further this many failed attempts, in case failing over did
+/// not succeed the first time.
+const HOME_RELAY_UNREACHABLE_ATTEMPTS: usize = 3;
+
+/// Time after which the relay protocol is tried again, once fallen back to websockets.
+const PREFERRED_PROTOCOL_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
 /// An actor which handles the connection to a single relay server.
 ///
 /// It is responsible for maintaining the connection to the relay server and handling all
@@ -146,6 +158,8 @@ struct ActiveRelayActor {
     url: RelayUrl,
     /// Builder which can repeatedly build a relay client.
     relay_client_builder: relay::client::ClientBuilder,
+    /// The protocol used to connect to the relay server.
+    protocol: ProtocolFallback,
     /// Whether or not this is the home relay server.
     ///
     /// The home relay server needs to maintain it's connection to the relay server, even if
@@ -159,6 +173,8 @@ struct ActiveRelayActor {
     inactive_timeout: Pin<Box<time::Sleep>>,
     /// Token indicating the [`ActiveRelayActor`] should stop.
     stop_token: CancellationToken,
+    /// Notified with our URL when we are the home relay but can not connect.
+    home_relay_unreachable: mpsc::Sender<RelayUrl>,
     metrics: Arc<MagicsockMetrics>,
 }
 
@@ -178,6 +194,8 @@ enum ActiveRelayMessage {
     #[cfg(test)]
     GetLocalAddr(oneshot::Sender<Option<SocketAddr>>),
     #[cfg(test)]
+    GetProtocol(oneshot::Sender<Option<relay::http::Protocol>>),
+    #[cfg(test)]
     PingServer(oneshot::Sender<()>),
 }
 
@@ -202,6 +220,7 @@ struct ActiveRelayActorOptions {
     relay_datagrams_recv: mpsc::Sender<RelayRecvDatagram>,
     connection_opts: RelayConnectionOptions,
     stop_token: CancellationToken,
+    home_relay_unreachable: mpsc::Sender<RelayUrl>,
     metrics: Arc<MagicsockMetrics>,
 }
 
@@ -276,6 +295,80 @@ enum DialError {
     },
 }
 
+impl DialError {
+    /// Returns whether the relay server was reached, but upgrading the connection failed.
+    ///
+    /// This is how proxies and middleboxes which do not pass the relay protocol's HTTP
+    /// upgrade usually fail.  Timeouts are not included, they do not tell whether the relay
+    /// server was reached at all.  Neither are handshake failures: the upgrade succeeded
+    /// and the relay server rejected us, e.g. because access was denied.
+    fn is_upgrade_failure(&self) -> bool {
+        match self {
+            DialError::Timeout { .. } => false,
+            DialError::Connect { source, .. } => matches!(
+                source.as_ref(),
+                ConnectError::UnexpectedUpgradeStatus { .. } | ConnectError::Upgrade { .. }
+            ),
+        }
+    }
+}
+
+/// Chooses the protocol to connect to a relay server with.
+///
+/// Some networks only allow HTTPS through proxies or middleboxes which do not pass the
+/// custom HTTP upgrade of the relay protocol, but do pass websocket upgrades.  If the
+/// connection was only established using websockets, websockets are used until
+/// [`PREFERRED_PROTOCOL_RETRY_INTERVAL`] passed or the network changed.
+///
+/// An HTTPS long-polling fallback for networks which block websockets as well is not
+/// implemented, the relay server has no endpoint for it.
+#[derive(Debug)]
+struct ProtocolFallback {
+    /// The configured protocol.
+    preferred: relay::http::Protocol,
+    /// When the connection fell back to websockets.
+    fallback_since: Option<Instant>,
+}
+
+impl ProtocolFallback {
+    fn new(preferred: relay::http::Protocol) -> Self {
+        Self {
+            preferred,
+            fallback_since: None,
+        }
+    }
+
+    /// Returns the protocol to connect with.
+    fn current(&mut self) -> relay::http::Protocol {
+        match self.fallback_since {
+            Some(since) if since.elapsed() < PREFERRED_PROTOCOL_RETRY_INTERVAL => {
+                relay::http::Protocol::Websocket
+            }
+            Some(_) => {
+                debug!(protocol = ?self.preferred, "retrying preferred relay protocol");
+                self.fallback_since = None;
+                self.preferred
+            }
+            None => self.preferred,
+        }
+    }
+
+    /// Records the protocol the connection was established with.
+    fn connected_with(&mut self, protocol: relay::http::Protocol) {
+        if protocol == self.preferred {
+            self.fallback_since = None;
+        } else if self.fallback_since.is_none() {
+            warn!(?protocol, "relay protocol unusable, falling back");
+            self.fallback_since = Some(Instant::now());
+        }
+    }
+
+    /// Tries the preferred protocol again on the next connection, after a network change.
+    fn reset(&mut self) {
+        self.fallback_since = None;
+    }
+}
+
 impl ActiveRelayActor {
     fn new(opts: ActiveRelayActorOptions) -> Self {
         let ActiveRelayActorOptions {
@@ -286,8 +379,10 @@ impl ActiveRelayActor {
             relay_datagrams_recv,
             connection_opts,
             stop_token,
+            home_relay_unreachable,
             metrics,
         } = opts;
+        let protocol = ProtocolFallback::new(connection_opts.protocol);
         let relay_client_builder = Self::create_relay_builder(url.clone(), connection_opts);
         ActiveRelayActor {
             prio_inbox,
@@ -296,9 +391,11 @@ impl ActiveRelayActor {
             relay_datagrams_send,
             url,
             relay_client_builder,
+            protocol,
             is_home_relay: false,
             inactive_timeout: Box::pin(time::sleep(RELAY_INACTIVE_CLEANUP_TIME)),
             stop_token,
+            home_relay_unreachable,
             metrics,
         }
     }
@@ -343,11 +440,18 @@ impl ActiveRelayActor {
         // self.metrics.num_relay_conns_added.inc();
 
         let mut backoff = Self::build_backoff();
+        let mut failed_attempts = 0;
 
         while let Err(err) = self.run_once().await {
             warn!("{err}");
             match err {
                 RelayConnectionError::Dial { .. } | RelayConnectionError::Handshake { .. } => {
+                    failed_attempts += 1;
+                    if self.is_home_relay && failed_attempts % HOME_RELAY_UNREACHABLE_ATTEMPTS == 0
+                    {
+                        warn!(%failed_attempts, "home relay unreachable");
+                        self.home_relay_unreachable.try_send(self.url.clone()).ok();
+                    }
                     // If dialing failed, or if the relay connection failed before we received a pong,
                     // we wait an exponentially increasing time until we attempt to reconnect again.
                     let Some(delay) = backoff.next() else {
@@ -361,6 +465,7 @@ impl ActiveRelayActor {
                     // If the relay connection remained established long enough so that we received a pong
                     // from the relay server, we reset the backoff and attempt to reconnect immediately.
                     backoff = Self::build_backoff();
+                    failed_attempts = 0;
                 }
             }
         }
@@ -385,11 +490,11 @@ impl ActiveRelayActor {
     /// or if the relay connection failed while connected. In both cases, the connection should
     /// be retried with a backoff.
     async fn run_once(&mut self) -> Result<(), RelayConnectionError> {
-        let client = match self.run_dialing().instrument(info_span!("dialing")).await {
+        let (client, protocol) = match self.run_dialing().instrument(info_span!("dialing")).await {
             Some(client_res) => client_res.context(DialSnafu)?,
             None => return Ok(()),
         };
-        self.run_connected(client)
+        self.run_connected(client, protocol)
             .instrument(info_span!("connected"))
             .await
     }
@@ -416,7 +521,9 @@ impl ActiveRelayActor {
     ///
     /// Returns `None` if the actor needs to shut down.  Returns `Some(Ok(client))` when the
     /// connection is established, and `Some(Err(err))` if dialing the relay failed.
-    async fn run_dialing(&mut self) -> Option<Result<iroh_relay::client::Client, DialError>> {
+    async fn run_dialing(
+        &mut self,
+    ) -> Option<Result<(iroh_relay::client::Client, relay::http::Protocol), DialError>> {
         debug!("Actor loop: connecting to relay.");
 
         // We regularly flush the relay_datagrams_send queue so it is not full of stale
@@ -451,8 +558,9 @@ impl ActiveRelayActor {
                 }
                 res = &mut dialing_fut => {
                     match res {
-                        Ok(client) => {
-                            break Some(Ok(client));
+                        Ok((client, protocol)) => {
+                            self.protocol.connected_with(protocol);
+                            break Some(Ok((client, protocol)));
                         }
                         Err(err) => {
                             break Some(Err(err));
@@ -468,12 +576,18 @@ impl ActiveRelayActor {
                         ActiveRelayMessage::SetHomeRelay(is_home) => {
                             self.set_home_relay(is_home);
                         }
-                        ActiveRelayMessage::CheckConnection(_local_ips) => {}
+                        ActiveRelayMessage::CheckConnection(_local_ips) => {
+                            self.protocol.reset();
+                        }
                         #[cfg(test)]
                         ActiveRelayMessage::GetLocalAddr(sender) => {
                             sender.send(None).ok();
                         }
                         #[cfg(test)]
+                        ActiveRelayMessage::GetProtocol(sender) => {
+                            sender.send(None).ok();
+                        }
+                        #[cfg(test)]
                         ActiveRelayMessage::PingServer(sender) => {
                             drop(sender);
                         }
@@ -503,13 +617,36 @@ impl ActiveRelayActor {
     /// connections.  It currently does not ever return `Err` as the retries continue
     /// forever.
     // This is using `impl Future` to return a future without a reference to self.
-    fn dial_relay(&self) -> impl Future<Output = Result<Client, DialError>> {
+    ///
+    /// If connecting with the relay protocol fails after reaching the relay server, this
+    /// also tries to connect using websockets.  The future resolves to the client and the
+    /// protocol it is connected with.
+    fn dial_relay(
+        &mut self,
+    ) -> impl Future<Output = Result<(Client, relay::http::Protocol), DialError>> {
+        let protocol = self.protocol.current();
         let client_builder = self.relay_client_builder.clone();
         async move {
-            match time::timeout(CONNECT_TIMEOUT, client_builder.connect()).await {
-                Ok(Ok(client)) => Ok(client),
-                Ok(Err(err)) => Err(ConnectSnafu.into_error(err)),
-                Err(_) => Err(TimeoutSnafu.build()),
+            let dial = |protocol| async move {
+                let client_builder = client_builder.protocol(protocol);
+                match time::timeout(CONNECT_TIMEOUT, client_builder.connect()).await {
+                    Ok(Ok(client)) => Ok((client, protocol)),
+                    Ok(Err(err)) => Err(ConnectSnafu.into_error(err)),
+                    Err(_) => Err(TimeoutSnafu.build()),
+                }
+            };
+            match dial.clone()(protocol).await {
+                Err(err)
+                    if protocol == relay::http::Protocol::Relay && err.is_upgrade_failure() =>
+                {
+                    debug!("relay protocol failed, trying websockets: {err:#}");
+                    // Only fall back if the relay server is reachable using websockets,
+                    // otherwise report the original error.
+                    dial(relay::http::Protocol::Websocket)
+                        .await
+                        .map_err(|_| err)
+                }
+                res => res,
             }
         }
     }
@@ -518,11 +655,13 @@ impl ActiveRelayActor {
     ///
     /// Returns `Ok` if the actor needs to shut down.  `Err` is returned if the connection
     /// to the relay server is lost.
+    #[allow(clippy::result_large_err)]
     async fn run_connected(
         &mut self,
         client: iroh_relay::client::Client,
+        protocol: relay::http::Protocol,
     ) -> Result<(), RelayConnectionError> {
-        debug!("Actor loop: connected to relay");
+        debug!(?protocol, "Actor loop: connected to relay");
         event!(
             target: "iroh::_events::relay::connected",
             Level::DEBUG,
@@ -593,6 +732,7 @@ impl ActiveRelayActor {
                             self.set_home_relay(is_home);
                         }
                         ActiveRelayMessage::CheckConnection(local_ips) => {
+                            self.protocol.reset();
                             match client_stream.local_addr() {
                                 Some(addr) if local_ips.contains(&addr.ip()) => {
                                     let data = state.ping_tracker.new_ping();
@@ -609,6 +749,10 @@ impl ActiveRelayActor {
                             sender.send(addr).ok();
                         }
                         #[cfg(test)]
+                        ActiveRelayMessage::GetProtocol(sender) => {
+                            sender.send(Some(protocol)).ok();
+                        }
+                        #[cfg(test)]
                         ActiveRelayMessage::PingServer(sender) => {
                             let data = rand::random();
                             state.test_pong = Some((data, sender));
@@ -875,6 +1019,8 @@ pub(super) struct RelayActor {
 #[derive(Debug)]
 pub struct Config {
     pub my_relay: Watchable<Option<RelayUrl>>,
+    /// Notified when the home relay can not be reached any more.
+    pub home_relay_unreachable: mpsc::Sender<RelayUrl>,
     pub secret_key: SecretKey,
     #[cfg(not(wasm_browser))]
     pub dns_resolver: DnsResolver,
@@ -1137,6 +1283,7 @@ impl RelayActor {
             relay_datagrams_recv: self.relay_datagram_recv_queue.clone(),
             connection_opts,
             stop_token: self.cancel_token.child_token(),
+            home_relay_unreachable: self.config.home_relay_unreachable.clone(),
             metrics: self.config.metrics.clone(),
         };
         let actor = ActiveRelayActor::new(opts);
@@ -1370,24 +1517,37 @@ impl Iterator for PacketSplitIter {
 #[cfg(test)]
 mod tests {
     use std::{
-        sync::{atomic::AtomicBool, Arc},
+        net::{Ipv4Addr, SocketAddr},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
         time::Duration,
     };
 
     use bytes::Bytes;
     use iroh_base::{NodeId, RelayUrl, SecretKey};
-    use iroh_relay::PingTracker;
+    use iroh_relay::{
+        http::Protocol,
+        server::{AccessConfig, RelayConfig, Server, ServerConfig},
+        PingTracker,
+    };
     use n0_snafu::{Error, Result, ResultExt};
     use smallvec::smallvec;
-    use tokio::sync::{mpsc, oneshot};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::{mpsc, oneshot},
+    };
     use tokio_util::{sync::CancellationToken, task::AbortOnDropHandle};
     use tracing::{info, info_span, Instrument};
     use tracing_test::traced_test;
 
     use super::{
         ActiveRelayActor, ActiveRelayActorOptions, ActiveRelayMessage, ActiveRelayPrioMessage,
-        PacketizeIter, RelayConnectionOptions, RelayRecvDatagram, RelaySendItem, MAX_PACKET_SIZE,
-        RELAY_INACTIVE_CLEANUP_TIME, UNDELIVERABLE_DATAGRAM_TIMEOUT,
+        PacketizeIter, ProtocolFallback, RelayConnectionOptions, RelayRecvDatagram, RelaySendItem,
+        MAX_PACKET_SIZE, PREFERRED_PROTOCOL_RETRY_INTERVAL, RELAY_INACTIVE_CLEANUP_TIME,
+        UNDELIVERABLE_DATAGRAM_TIMEOUT,
     };
     use crate::{dns::DnsResolver, test_utils};
 
@@ -1449,6 +1609,7 @@ mod tests {
                 protocol: iroh_relay::http::Protocol::default(),
             },
             stop_token,
+            home_relay_unreachable: mpsc::channel(1).0,
             metrics: Default::default(),
         };
         let task = tokio::spawn(ActiveRelayActor::new(opts).run().instrument(span));
@@ -1647,6 +1808,156 @@ mod tests {
         Ok(())
     }
 
+    /// Runs a proxy in front of the relay server at `relay_addr`.
+    ///
+    /// Like some middleboxes, the proxy rejects the relay protocol's HTTP upgrade unless
+    /// `allow_relay` is set, but always passes websocket upgrades.
+    async fn run_upgrade_filtering_proxy(
+        relay_addr: SocketAddr,
+        allow_relay: Arc<AtomicBool>,
+    ) -> Result<(SocketAddr, AbortOnDropHandle<()>)> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .context("bind")?;
+        let addr = listener.local_addr().context("local addr")?;
+        let task = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let allow_relay = allow_relay.load(Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let is_websocket = String::from_utf8_lossy(&request)
+                        .to_lowercase()
+                        .contains("upgrade: websocket");
+                    if !is_websocket && !allow_relay {
+                        stream
+                            .write_all(b"HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\n\r\n")
+                            .await
+                            .ok();
+                        return;
+                    }
+                    let Ok(mut upstream) = TcpStream::connect(relay_addr).await else {
+                        return;
+                    };
+                    if upstream.write_all(&request).await.is_ok() {
+                        tokio::io::copy_bidirectional(&mut stream, &mut upstream)
+                            .await
+                            .ok();
+                    }
+                });
+            }
+        });
+        Ok((addr, AbortOnDropHandle::new(task)))
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_active_relay_websocket_fallback() -> Result {
+        let server = Server::spawn(ServerConfig::<(), ()> {
+            relay: Some(RelayConfig {
+                http_bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+                tls: None,
+                limits: Default::default(),
+                key_cache_capacity: Some(1024),
+                access: AccessConfig::Everyone,
+                mesh: None,
+                admin_bind_addr: None,
+            }),
+            ..Default::default()
+        })
+        .await?;
+        let relay_addr = server.http_addr().context("no http addr")?;
+        let relay_url: RelayUrl = format!("http://{relay_addr}").parse()?;
+        let allow_relay = Arc::new(AtomicBool::new(false));
+        let (proxy_addr, _proxy_task) =
+            run_upgrade_filtering_proxy(relay_addr, allow_relay.clone()).await?;
+        let proxy_url: RelayUrl = format!("http://{proxy_addr}").parse()?;
+        let (peer_node, _echo_node_task) = start_echo_node(relay_url.clone());
+
+        let secret_key = SecretKey::from_bytes(&[1u8; 32]);
+        let (datagram_recv_tx, mut datagram_recv_rx) = mpsc::channel(16);
+        let (send_datagram_tx, send_datagram_rx) = mpsc::channel(16);
+        let (_prio_inbox_tx, prio_inbox_rx) = mpsc::channel(8);
+        let (inbox_tx, inbox_rx) = mpsc::channel(16);
+        let cancel_token = CancellationToken::new();
+        let _task = start_active_relay_actor(
+            secret_key,
+            cancel_token.clone(),
+            proxy_url.clone(),
+            prio_inbox_rx,
+            inbox_rx,
+            send_datagram_rx,
+            datagram_recv_tx,
+            info_span!("actor-under-test"),
+        );
+        let _guard = cancel_token.drop_guard();
+
+        let get_protocol = || async {
+            let (tx, rx) = oneshot::channel();
+            inbox_tx
+                .send(ActiveRelayMessage::GetProtocol(tx))
+                .await
+                .context("send get protocol msg")?;
+            rx.await.context("recv get protocol msg")
+        };
+
+        // The relay protocol is rejected, but the relay is reachable using websockets.
+        info!("echo using websockets");
+        let hello_send_item = RelaySendItem {
+            remote_node: peer_node,
+            url: proxy_url.clone(),
+            datagrams: smallvec![Bytes::from_static(b"hello")],
+        };
+        send_recv_echo(
+            hello_send_item.clone(),
+            &send_datagram_tx,
+            &mut datagram_recv_rx,
+        )
+        .await?;
+        assert_eq!(get_protocol().await?, Some(Protocol::Websocket));
+
+        // After a network change the relay protocol is tried again.
+        info!("network change");
+        allow_relay.store(true, Ordering::Relaxed);
+        inbox_tx
+            .send(ActiveRelayMessage::CheckConnection(Vec::new()))
+            .await
+            .context("send check connection msg")?;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while get_protocol().await? != Some(Protocol::Relay) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Ok::<_, Error>(())
+        })
+        .await
+        .context("timeout")??;
+        info!("echo using the relay protocol");
+        send_recv_echo(hello_send_item, &send_datagram_tx, &mut datagram_recv_rx).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_protocol_fallback_retry() {
+        let mut fallback = ProtocolFallback::new(Protocol::Relay);
+        assert_eq!(fallback.current(), Protocol::Relay);
+
+        fallback.connected_with(Protocol::Websocket);
+        assert_eq!(fallback.current(), Protocol::Websocket);
+        tokio::time::sleep(PREFERRED_PROTOCOL_RETRY_INTERVAL).await;
+        assert_eq!(fallback.current(), Protocol::Relay);
+
+        fallback.connected_with(Protocol::Websocket);
+        fallback.reset();
+        assert_eq!(fallback.current(), Protocol::Relay);
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_active_relay_inactive() -> Result {
@@ -1717,6 +2028,56 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_active_relay_home_unreachable() -> Result {
+        // Nothing listens on this port, so every dial attempt fails.
+        let relay_url: RelayUrl = "http://127.0.0.1:1".parse().unwrap();
+
+        let (datagram_recv_tx, _datagram_recv_rx) = mpsc::channel(16);
+        let (_send_datagram_tx, send_datagram_rx) = mpsc::channel(16);
+        let (_prio_inbox_tx, prio_inbox_rx) = mpsc::channel(8);
+        let (inbox_tx, inbox_rx) = mpsc::channel(16);
+        let (unreachable_tx, mut unreachable_rx) = mpsc::channel(1);
+        let cancel_token = CancellationToken::new();
+        let opts = ActiveRelayActorOptions {
+            url: relay_url.clone(),
+            prio_inbox_: prio_inbox_rx,
+            inbox: inbox_rx,
+            relay_datagrams_send: send_datagram_rx,
+            relay_datagrams_recv: datagram_recv_tx,
+            connection_opts: RelayConnectionOptions {
+                secret_key: SecretKey::from_bytes(&[1u8; 32]),
+                dns_resolver: DnsResolver::new(),
+                proxy_url: None,
+                prefer_ipv6: Arc::new(AtomicBool::new(false)),
+                insecure_skip_cert_verify: true,
+                protocol: iroh_relay::http::Protocol::default(),
+            },
+            stop_token: cancel_token.clone(),
+            home_relay_unreachable: unreachable_tx,
+            metrics: Default::default(),
+        };
+        let _task = AbortOnDropHandle::new(tokio::spawn(
+            ActiveRelayActor::new(opts)
+                .run()
+                .instrument(info_span!("actor-under-test")),
+        ));
+        inbox_tx
+            .send(ActiveRelayMessage::SetHomeRelay(true))
+            .await
+            .context("send")?;
+
+        let url = tokio::time::timeout(Duration::from_secs(10), unreachable_rx.recv())
+            .await
+            .context("timeout")?
+            .context("closed")?;
+        assert_eq!(url, relay_url);
+
+        cancel_token.cancel();
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_ping_tracker() {
         tokio::time::pause();
@@ -46,6 +46,7 @@ use self::reportgen::QadProbeReport;
 use self::reportgen::{ProbeFinished, ProbeReport};
 
 mod defaults;
+mod health;
 mod ip_mapped_addrs;
 mod metrics;
 mod probes;
@@ -75,14 +76,16 @@ pub(crate) mod portmapper {
 
 pub(crate) use ip_mapped_addrs::{IpMappedAddr, IpMappedAddresses};
 
+pub(crate) use self::health::RelayHealthTracker;
 pub(crate) use self::reportgen::IfStateDetails;
 #[cfg(not(wasm_browser))]
 use self::reportgen::SocketState;
 pub use self::{
+    health::{LatencySample, RelayHealth},
     metrics::Metrics,
     options::Options,
     probes::Probe,
-    report::{RelayLatencies, Report},
+    report::{NatMapping, NatReport, RelayLatencies, Report},
     reportgen::QuicConfig,
 };
 use crate::util::MaybeFuture;
@@ -250,6 +253,14 @@ impl Client {
         }
     }
 
+    /// Replaces the relay servers to probe.
+    ///
+    /// The next report will be a full report, probing all relay servers of the new map.
+    pub(crate) fn set_relay_map(&mut self, relay_map: RelayMap) {
+        self.relay_map = relay_map;
+        self.reports.next_full = true;
+    }
+
     /// Generates a [`Report`].
     ///
     /// Look at [`Options`] for the different configuration options.
@@ -367,6 +378,9 @@ impl Client {
                             }
                             Err(err) => {
                                 trace!("probe errored: {:?}", err);
+                                if let Some(url) = err.failed_relay() {
+                                    report.failed_relays.insert(url.clone());
+                                }
                             }
                         },
                         #[cfg(not(wasm_browser))]
@@ -378,6 +392,11 @@ impl Client {
             }
         }
 
+        // Relays are only unreachable if none of their probes succeeded.
+        let relay_latency = &report.relay_latency;
+        report
+            .failed_relays
+            .retain(|url| relay_latency.get(url).is_none());
         self.add_report_history_and_set_preferred_relay(&mut report);
         debug!(
             ?report,
@@ -0,0 +1,190 @@
+//! Health information about the relay servers, collected over many net_reports.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use iroh_base::RelayUrl;
+use iroh_relay::RelayMap;
+use n0_future::time::{Duration, SystemTime};
+
+use super::Report;
+
+/// The number of latency samples kept per relay server.
+const MAX_LATENCY_SAMPLES: usize = 32;
+
+/// Health information about a relay server.
+///
+/// This is collected from the net_reports run by an endpoint and from its connection to
+/// the home relay, see [`Endpoint::relay_health`].
+///
+/// [`Endpoint::relay_health`]: crate::Endpoint::relay_health
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RelayHealth {
+    /// The most recent latency samples, oldest first.
+    ///
+    /// At most 32 samples are kept.
+    pub latencies: VecDeque<LatencySample>,
+    /// When this relay server last failed.
+    ///
+    /// This is when it could not be reached by any probe of a net_report, or when the
+    /// connection to it failed while it was the home relay.
+    pub last_failure: Option<SystemTime>,
+    /// How often this relay server was chosen as the home relay.
+    pub home_count: u64,
+    /// Whether this relay server is the current home relay.
+    pub is_home: bool,
+}
+
+impl RelayHealth {
+    /// Returns the most recent latency sample.
+    pub fn latest_latency(&self) -> Option<Duration> {
+        self.latencies.back().map(|sample| sample.latency)
+    }
+}
+
+/// A latency measured to a relay server by a net_report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySample {
+    /// When the net_report completed.
+    pub at: SystemTime,
+    /// The lowest latency measured across all probes of the net_report.
+    pub latency: Duration,
+}
+
+/// Collects the [`RelayHealth`] of all relay servers.
+#[derive(Debug, Default)]
+pub(crate) struct RelayHealthTracker {
+    relays: BTreeMap<RelayUrl, RelayHealth>,
+    home: Option<RelayUrl>,
+}
+
+impl RelayHealthTracker {
+    /// Records the latencies and the preferred relay of a completed net_report.
+    pub(crate) fn on_report(&mut self, report: &Report) {
+        let at = SystemTime::now();
+        let mut latencies = BTreeMap::<&RelayUrl, Duration>::new();
+        for (url, latency) in report.relay_latency.iter() {
+            latencies
+                .entry(url)
+                .and_modify(|l| *l = (*l).min(latency))
+                .or_insert(latency);
+        }
+        for (url, latency) in latencies {
+            let health = self.relays.entry(url.clone()).or_default();
+            if health.latencies.len() == MAX_LATENCY_SAMPLES {
+                health.latencies.pop_front();
+            }
+            health.latencies.push_back(LatencySample { at, latency });
+        }
+        for url in &report.failed_relays {
+            self.relays.entry(url.clone()).or_default().last_failure = Some(at);
+        }
+
+        if let Some(url) = &report.preferred_relay {
+            if self.home.as_ref() != Some(url) {
+                if let Some(health) = self.home.as_ref().and_then(|h| self.relays.get_mut(h)) {
+                    health.is_home = false;
+                }
+                let health = self.relays.entry(url.clone()).or_default();
+                health.home_count += 1;
+                health.is_home = true;
+                self.home = Some(url.clone());
+            }
+        }
+    }
+
+    /// Records that the relay server became unreachable.
+    pub(crate) fn on_failure(&mut self, url: &RelayUrl) {
+        self.relays.entry(url.clone()).or_default().last_failure = Some(SystemTime::now());
+    }
+
+    /// Forgets the relay servers which are no longer in the relay map.
+    pub(crate) fn retain(&mut self, relay_map: &RelayMap) {
+        self.relays.retain(|url, _| relay_map.contains_node(url));
+        if self
+            .home
+            .as_ref()
+            .is_some_and(|url| !relay_map.contains_node(url))
+        {
+            self.home = None;
+        }
+    }
+
+    /// Returns the health information of all relay servers.
+    pub(crate) fn get(&self) -> BTreeMap<RelayUrl, RelayHealth> {
+        self.relays.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net_report::Probe;
+
+    fn relay_url(i: u16) -> RelayUrl {
+        format!("http://{i}.com").parse().unwrap()
+    }
+
+    fn report(latencies: &[(u16, u64)], preferred: u16) -> Report {
+        let mut report = Report::default();
+        for (i, ms) in latencies {
+            report.relay_latency.update_relay(
+                relay_url(*i),
+                Duration::from_millis(*ms),
+                Probe::Https,
+            );
+        }
+        report.preferred_relay = Some(relay_url(preferred));
+        report
+    }
+
+    #[test]
+    fn test_relay_health() {
+        let mut tracker = RelayHealthTracker::default();
+        tracker.on_report(&report(&[(1, 10), (2, 20)], 1));
+        tracker.on_report(&report(&[(1, 30), (2, 15)], 1));
+        tracker.on_report(&report(&[(2, 5)], 2));
+        tracker.on_failure(&relay_url(2));
+        tracker.on_report(&report(&[(1, 10)], 1));
+
+        let health = tracker.get();
+        let one = &health[&relay_url(1)];
+        assert_eq!(one.latencies.len(), 3);
+        assert_eq!(one.latest_latency(), Some(Duration::from_millis(10)));
+        assert_eq!(one.home_count, 2);
+        assert!(one.is_home);
+        assert!(one.last_failure.is_none());
+
+        let two = &health[&relay_url(2)];
+        assert_eq!(two.latencies.len(), 3);
+        assert_eq!(two.home_count, 1);
+        assert!(!two.is_home);
+        assert!(two.last_failure.is_some());
+
+        for _ in 0..MAX_LATENCY_SAMPLES {
+            tracker.on_report(&report(&[(1, 10)], 1));
+        }
+        assert_eq!(
+            tracker.get()[&relay_url(1)].latencies.len(),
+            MAX_LATENCY_SAMPLES
+        );
+    }
+
+    #[test]
+    fn test_relay_health_failed_probes() {
+        let mut tracker = RelayHealthTracker::default();
+        let mut r = report(&[(1, 10)], 1);
+        r.failed_relays.insert(relay_url(2));
+        tracker.on_report(&r);
+
+        let health = tracker.get();
+        assert!(health[&relay_url(1)].last_failure.is_none());
+        assert!(health[&relay_url(2)].last_failure.is_some());
+
+        // Relays removed from the relay map are forgotten.
+        tracker.retain(&RelayMap::from(relay_url(1)));
+        let health = tracker.get();
+        assert!(health.contains_key(&relay_url(1)));
+        assert!(!health.contains_key(&relay_url(2)));
+    }
+}
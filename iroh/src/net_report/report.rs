@@ -1,5 +1,5 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     fmt,
     net::{SocketAddr, SocketAddrV4, SocketAddrV6},
     time::Duration,
@@ -26,6 +26,8 @@ pub struct Report {
     pub preferred_relay: Option<RelayUrl>,
     /// keyed by relay Url
     pub relay_latency: RelayLatencies,
+    /// The relay servers which were probed, but could not be reached by any probe.
+    pub failed_relays: BTreeSet<RelayUrl>,
     /// ip:port of global IPv4
     pub global_v4: Option<SocketAddrV4>,
     /// `[ip]:port` of global IPv6
@@ -60,6 +62,17 @@ impl Report {
         }
     }
 
+    /// Classifies the NAT this node is behind, based on the QAD probes of this report.
+    ///
+    /// See [`NatReport`] for what can and can not be derived from the probes.
+    pub fn nat_report(&self) -> NatReport {
+        NatReport {
+            mapping_v4: NatMapping::classify(self.udp_v4, self.mapping_varies_by_dest_ipv4),
+            mapping_v6: NatMapping::classify(self.udp_v6, self.mapping_varies_by_dest_ipv6),
+            captive_portal: self.captive_portal,
+        }
+    }
+
     /// Updates a net_report [`Report`] with a new [`ProbeReport`].
     pub(super) fn update(&mut self, report: &ProbeReport) {
         match report {
@@ -126,6 +139,79 @@ impl Report {
     }
 }
 
+/// A classification of the NAT a node is behind, derived from a [`Report`].
+///
+/// The public addresses reported by the relays' QUIC address discovery (QAD) only allow
+/// classifying the *mapping* behaviour of the NAT: whether the same local socket is mapped
+/// to the same public address regardless of the destination.  Filtering behaviour and
+/// hairpinning can not be observed from these probes and are not reported.
+///
+/// Obtained using [`Endpoint::nat_report`] or [`Report::nat_report`].
+///
+/// [`Endpoint::nat_report`]: crate::Endpoint::nat_report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct NatReport {
+    /// The mapping behaviour for IPv4, `None` if there is no IPv4 UDP connectivity.
+    pub mapping_v4: Option<NatMapping>,
+    /// The mapping behaviour for IPv6, `None` if there is no IPv6 UDP connectivity.
+    pub mapping_v6: Option<NatMapping>,
+    /// Whether a captive portal was detected, `None` if this was not checked.
+    pub captive_portal: Option<bool>,
+}
+
+impl NatReport {
+    /// Whether there is any UDP connectivity at all.
+    ///
+    /// Without UDP connectivity all connections will use a relay server.
+    pub fn has_udp(&self) -> bool {
+        self.mapping_v4.is_some() || self.mapping_v6.is_some()
+    }
+
+    /// Whether direct connections to other nodes are likely to be established.
+    ///
+    /// This is the case if any address family has an endpoint-independent mapping.  With an
+    /// endpoint-dependent mapping holepunching only succeeds if the remote node is not
+    /// behind a NAT itself, or behind a NAT which is more permissive.
+    pub fn direct_connections_likely(&self) -> bool {
+        [self.mapping_v4, self.mapping_v6].contains(&Some(NatMapping::EndpointIndependent))
+    }
+}
+
+/// The mapping behaviour of a NAT, as defined in [RFC 4787].
+///
+/// [RFC 4787]: https://www.rfc-editor.org/rfc/rfc4787#section-4.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NatMapping {
+    /// The public address is the same for all destinations.
+    ///
+    /// This is the case for nodes which are not behind a NAT and for "easy" NATs, which
+    /// allow holepunching.
+    EndpointIndependent,
+    /// The public address differs between destinations.
+    ///
+    /// This covers both address-dependent and address-and-port-dependent mappings, which
+    /// can not be distinguished by the probes.  These are commonly called symmetric NATs and
+    /// make holepunching unlikely to succeed.
+    EndpointDependent,
+    /// UDP works, but fewer than two relays responded so the mapping could not be compared.
+    Unknown,
+}
+
+impl NatMapping {
+    fn classify(udp: bool, mapping_varies_by_dest: Option<bool>) -> Option<Self> {
+        if !udp {
+            return None;
+        }
+        Some(match mapping_varies_by_dest {
+            Some(false) => Self::EndpointIndependent,
+            Some(true) => Self::EndpointDependent,
+            None => Self::Unknown,
+        })
+    }
+}
+
 /// Latencies per relay node.
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct RelayLatencies {
@@ -212,3 +298,38 @@ impl RelayLatencies {
         list.into_iter().min()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nat_report() {
+        let report = Report::default();
+        let nat = report.nat_report();
+        assert!(!nat.has_udp());
+        assert!(!nat.direct_connections_likely());
+
+        let report = Report {
+            udp_v4: true,
+            mapping_varies_by_dest_ipv4: Some(true),
+            udp_v6: true,
+            ..Default::default()
+        };
+        let nat = report.nat_report();
+        assert_eq!(nat.mapping_v4, Some(NatMapping::EndpointDependent));
+        assert_eq!(nat.mapping_v6, Some(NatMapping::Unknown));
+        assert!(nat.has_udp());
+        assert!(!nat.direct_connections_likely());
+
+        let report = Report {
+            udp_v4: true,
+            mapping_varies_by_dest_ipv4: Some(false),
+            ..Default::default()
+        };
+        let nat = report.nat_report();
+        assert_eq!(nat.mapping_v4, Some(NatMapping::EndpointIndependent));
+        assert_eq!(nat.mapping_v6, None);
+        assert!(nat.direct_connections_likely());
+    }
+}
@@ -176,13 +176,29 @@ struct Actor {
 #[snafu(module)]
 pub(super) enum ProbesError {
     #[snafu(display("Probe failed"))]
-    ProbeFailure { source: ProbeError },
+    ProbeFailure { url: RelayUrl, source: ProbeError },
     #[snafu(display("All probes failed"))]
     AllProbesFailed,
     #[snafu(display("Probe cancelled"))]
     Cancelled,
     #[snafu(display("Probe timed out"))]
-    Timeout,
+    Timeout { url: RelayUrl },
+}
+
+impl ProbesError {
+    /// Returns the relay server the probe failed for, if it could not be reached.
+    ///
+    /// Probes which were aborted because they were no longer needed are not failures.
+    pub(super) fn failed_relay(&self) -> Option<&RelayUrl> {
+        match self {
+            Self::ProbeFailure {
+                url,
+                source: ProbeError::Https { .. },
+            }
+            | Self::Timeout { url } => Some(url),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -361,6 +377,7 @@ impl Actor {
             let proto = probe_set.proto();
             for (delay, relay_node) in probe_set.params() {
                 let probe_token = set_token.child_token();
+                let url = relay_node.url.clone();
 
                 let fut = probe_token.run_until_cancelled_owned(time::timeout(
                     PROBES_TIMEOUT,
@@ -380,10 +397,10 @@ impl Actor {
                             Some(Ok(Ok(report))) => Ok(report),
                             Some(Ok(Err(err))) => {
                                 warn!("probe failed: {:#}", err);
-                                Err(probes_error::ProbeFailureSnafu {}.into_error(err))
+                                Err(probes_error::ProbeFailureSnafu { url }.into_error(err))
                             }
                             Some(Err(time::Elapsed { .. })) => {
-                                Err(probes_error::TimeoutSnafu.build())
+                                Err(probes_error::TimeoutSnafu { url }.build())
                             }
                             None => Err(probes_error::CancelledSnafu.build()),
                         };
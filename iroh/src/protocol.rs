@@ -45,6 +45,7 @@ use n0_future::{
     task::{self, AbortOnDropHandle, JoinSet},
 };
 use snafu::{Backtrace, Snafu};
+use tokio::sync::Semaphore;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info_span, trace, warn, Instrument};
 
@@ -113,6 +114,8 @@ pub enum AcceptError {
     MissingRemoteNodeId { source: RemoteNodeIdError },
     #[snafu(display("Not allowed."))]
     NotAllowed {},
+    #[snafu(display("Too many connections."))]
+    TooManyConnections {},
 
     #[snafu(transparent)]
     User {
@@ -564,6 +567,61 @@ impl<P: ProtocolHandler + Clone> ProtocolHandler for AccessLimit<P> {
     }
 }
 
+/// Wraps an existing protocol, limiting the number of concurrent connections.
+///
+/// A connection counts towards the limit from when its handshake starts until it is
+/// closed, even if the wrapped [`ProtocolHandler::accept`] returns earlier.  Since the limit
+/// applies per wrapped protocol, a router can use different limits for each ALPN.
+///
+/// Any refused connection will be closed with an error code of `0` and reason
+/// `too many connections`.
+#[derive(derive_more::Debug, Clone)]
+pub struct ConnectionLimit<P: ProtocolHandler + Clone> {
+    proto: P,
+    #[debug("{}", permits.available_permits())]
+    permits: Arc<Semaphore>,
+}
+
+impl<P: ProtocolHandler + Clone> ConnectionLimit<P> {
+    /// Create a new `ConnectionLimit`, allowing at most `max_connections` connections at
+    /// the same time.
+    pub fn new(proto: P, max_connections: usize) -> Self {
+        Self {
+            proto,
+            permits: Arc::new(Semaphore::new(max_connections)),
+        }
+    }
+}
+
+impl<P: ProtocolHandler + Clone> ProtocolHandler for ConnectionLimit<P> {
+    async fn on_connecting(&self, conn: Connecting) -> Result<Connection, AcceptError> {
+        // Taken before the handshake, so connections still handshaking count as well.
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            // The handshake is only completed to tell the remote why it is refused.
+            let conn = conn.await?;
+            conn.close(0u32.into(), b"too many connections");
+            return Err(TooManyConnectionsSnafu.build());
+        };
+        let conn = self.proto.on_connecting(conn).await?;
+        // The permit is held until the connection closes, however the wrapped protocol's
+        // `accept` ends.
+        let closed = conn.clone();
+        task::spawn(async move {
+            closed.closed().await;
+            drop(permit);
+        });
+        Ok(conn)
+    }
+
+    fn accept(&self, conn: Connection) -> impl Future<Output = Result<(), AcceptError>> + Send {
+        self.proto.accept(conn)
+    }
+
+    fn shutdown(&self) -> impl Future<Output = ()> + Send {
+        self.proto.shutdown()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{sync::Mutex, time::Duration};
@@ -643,6 +701,104 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_connection_limit() -> Result {
+        let e1 = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let proto = ConnectionLimit::new(Echo, 1);
+        let r1 = Router::builder(e1.clone()).accept(ECHO_ALPN, proto).spawn();
+        let addr1 = r1.endpoint().node_addr().initialized().await?;
+
+        let e2 = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let conn = e2.connect(addr1.clone(), ECHO_ALPN).await?;
+        let (mut send, mut recv) = conn.open_bi().await.e()?;
+        send.write_all(b"hello").await.e()?;
+        send.finish().e()?;
+        assert_eq!(recv.read_to_end(1000).await.e()?, b"hello");
+
+        // The first connection is still open, so a second one is refused.
+        let e3 = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let conn2 = e3.connect(addr1.clone(), ECHO_ALPN).await?;
+        let (_send, mut recv) = conn2.open_bi().await.e()?;
+        let response = recv.read_to_end(1000).await.unwrap_err();
+        assert!(format!("{response:#?}").contains("too many connections"));
+
+        // After closing the first connection there is room again.
+        conn.close(0u32.into(), b"done");
+        conn.closed().await;
+        let conn3 = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let conn = e3.connect(addr1.clone(), ECHO_ALPN).await?;
+                let (mut send, mut recv) = conn.open_bi().await.e()?;
+                send.write_all(b"again").await.e()?;
+                send.finish().e()?;
+                if recv.read_to_end(1000).await.is_ok() {
+                    break Ok::<_, n0_snafu::Error>(conn);
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .e()??;
+        conn3.close(0u32.into(), b"done");
+
+        r1.shutdown().await.e()?;
+        e2.close().await;
+        e3.close().await;
+
+        Ok(())
+    }
+
+    /// A connection still counts towards the limit if the wrapped protocol fails.
+    #[tokio::test]
+    async fn test_connection_limit_accept_error() -> Result {
+        #[derive(Debug, Clone)]
+        struct Failing;
+
+        impl ProtocolHandler for Failing {
+            async fn accept(&self, _connection: Connection) -> Result<(), AcceptError> {
+                Err(AcceptError::from_err(std::io::Error::other("failing")))
+            }
+        }
+
+        let e1 = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let proto = ConnectionLimit::new(Failing, 1);
+        let r1 = Router::builder(e1.clone()).accept(ECHO_ALPN, proto).spawn();
+        let addr1 = r1.endpoint().node_addr().initialized().await?;
+
+        let e2 = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let conn = e2.connect(addr1.clone(), ECHO_ALPN).await?;
+
+        let e3 = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let conn2 = e3.connect(addr1.clone(), ECHO_ALPN).await?;
+        let reason = conn2.closed().await;
+        assert!(format!("{reason:#?}").contains("too many connections"));
+
+        conn.close(0u32.into(), b"done");
+        r1.shutdown().await.e()?;
+        e2.close().await;
+        e3.close().await;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_graceful_shutdown() -> Result {
         #[derive(Debug, Clone, Default)]
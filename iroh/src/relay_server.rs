@@ -0,0 +1,303 @@
+//! Running a relay server in-process.
+//!
+//! Applications and tests which want their own relay server do not need to run the
+//! separate `iroh-relay` binary with a configuration file, they can spawn one using
+//! [`RelayServer::builder`]:
+//!
+//! ```no_run
+//! # use iroh::{relay_server::RelayServer, Endpoint, RelayMode};
+//! # async fn wrapper() -> n0_snafu::Result {
+//! let server = RelayServer::builder().spawn().await?;
+//! let ep = Endpoint::builder()
+//!     .relay_mode(RelayMode::Custom(server.relay_map()))
+//!     .bind()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use iroh_base::RelayUrl;
+pub use iroh_relay::server::{
+    Access, AccessConfig, ClientRateLimit, ClientStats, Limits, SpawnError, SupervisorError,
+};
+use iroh_relay::{
+    server::{CertConfig, QuicConfig, RelayConfig, Server, ServerConfig, TlsConfig},
+    RelayMap, RelayNode, RelayQuicConfig,
+};
+use nested_enum_utils::common_fields;
+use snafu::{Backtrace, Snafu};
+
+/// A relay server running in-process.
+///
+/// Created using [`RelayServer::builder`].  Dropping this will stop the server.
+#[derive(Debug)]
+pub struct RelayServer {
+    server: Server,
+    url: RelayUrl,
+}
+
+impl RelayServer {
+    /// Returns a builder to configure and spawn a [`RelayServer`].
+    pub fn builder() -> RelayServerBuilder {
+        RelayServerBuilder::default()
+    }
+
+    /// The URL clients use to connect to this relay server.
+    ///
+    /// This is an `https` URL if TLS is configured and an `http` URL otherwise.
+    pub fn url(&self) -> RelayUrl {
+        self.url.clone()
+    }
+
+    /// The [`RelayNode`] describing this relay server.
+    pub fn relay_node(&self) -> RelayNode {
+        RelayNode {
+            url: self.url.clone(),
+            quic: self
+                .server
+                .quic_addr()
+                .map(|addr| RelayQuicConfig { port: addr.port() }),
+        }
+    }
+
+    /// A [`RelayMap`] containing only this relay server.
+    pub fn relay_map(&self) -> RelayMap {
+        self.relay_node().into()
+    }
+
+    /// The socket address of the HTTP server.
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.server.http_addr()
+    }
+
+    /// The socket address of the HTTPS server, if TLS is configured.
+    pub fn https_addr(&self) -> Option<SocketAddr> {
+        self.server.https_addr()
+    }
+
+    /// The socket address of the QUIC address discovery server, if enabled.
+    pub fn quic_addr(&self) -> Option<SocketAddr> {
+        self.server.quic_addr()
+    }
+
+    /// Returns the traffic statistics of all currently connected clients.
+    ///
+    /// See [`Server::client_stats`] for details.
+    pub fn client_stats(&self) -> Vec<ClientStats> {
+        self.server.client_stats()
+    }
+
+    /// Returns the underlying relay [`Server`].
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Requests graceful shutdown and waits for the server to stop.
+    pub async fn shutdown(self) -> Result<(), SupervisorError> {
+        self.server.shutdown().await
+    }
+}
+
+/// Error when spawning a [`RelayServer`].
+#[common_fields({
+    backtrace: Option<Backtrace>,
+    #[snafu(implicit)]
+    span_trace: n0_snafu::SpanTrace,
+})]
+#[allow(missing_docs)]
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum RelayServerError {
+    #[snafu(display("QUIC address discovery requires TLS"))]
+    QuicWithoutTls {},
+    #[snafu(transparent)]
+    Spawn { source: SpawnError },
+}
+
+/// TLS configuration for a [`RelayServer`].
+#[derive(Debug)]
+struct RelayServerTls {
+    certs: Vec<rustls::pki_types::CertificateDer<'static>>,
+    server_config: rustls::ServerConfig,
+}
+
+/// Builder for a [`RelayServer`].
+///
+/// By default the relay server serves plain HTTP on a random port on localhost, without
+/// QUIC address discovery and without any limits.
+#[derive(Debug)]
+pub struct RelayServerBuilder {
+    http_bind_addr: SocketAddr,
+    https_bind_addr: SocketAddr,
+    quic_bind_addr: Option<SocketAddr>,
+    tls: Option<RelayServerTls>,
+    limits: Limits,
+    access: AccessConfig,
+    key_cache_capacity: Option<usize>,
+}
+
+impl Default for RelayServerBuilder {
+    fn default() -> Self {
+        Self {
+            http_bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+            https_bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+            quic_bind_addr: None,
+            tls: None,
+            limits: Limits::default(),
+            access: AccessConfig::Everyone,
+            key_cache_capacity: None,
+        }
+    }
+}
+
+impl RelayServerBuilder {
+    /// Sets the socket address to serve HTTP on.
+    ///
+    /// Without TLS all relay services are served on this address, otherwise only the
+    /// captive portal detection is.
+    pub fn http_bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.http_bind_addr = addr;
+        self
+    }
+
+    /// Sets the socket address to serve HTTPS on, used only if TLS is configured.
+    pub fn https_bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.https_bind_addr = addr;
+        self
+    }
+
+    /// Serves the relay over TLS using the given certificate chain and server config.
+    pub fn tls(
+        mut self,
+        certs: Vec<rustls::pki_types::CertificateDer<'static>>,
+        server_config: rustls::ServerConfig,
+    ) -> Self {
+        self.tls = Some(RelayServerTls {
+            certs,
+            server_config,
+        });
+        self
+    }
+
+    /// Enables QUIC address discovery, serving it on the given socket address.
+    ///
+    /// This requires TLS to be configured using [`Self::tls`], otherwise spawning the
+    /// server fails with [`RelayServerError::QuicWithoutTls`].
+    pub fn quic(mut self, bind_addr: SocketAddr) -> Self {
+        self.quic_bind_addr = Some(bind_addr);
+        self
+    }
+
+    /// Sets the rate limits and quotas of the relay server.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets which nodes are allowed to use the relay server.
+    pub fn access(mut self, access: AccessConfig) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Sets the capacity of the cache for public keys.
+    pub fn key_cache_capacity(mut self, capacity: usize) -> Self {
+        self.key_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Spawns the relay server.
+    pub async fn spawn(self) -> Result<RelayServer, RelayServerError> {
+        let quic_bind_addr = self.quic_bind_addr;
+        snafu::ensure!(
+            quic_bind_addr.is_none() || self.tls.is_some(),
+            QuicWithoutTlsSnafu
+        );
+        let tls = self.tls.map(|tls| TlsConfig {
+            cert: CertConfig::<(), ()>::Manual { certs: tls.certs },
+            https_bind_addr: self.https_bind_addr,
+            quic_bind_addr: quic_bind_addr.unwrap_or((Ipv4Addr::LOCALHOST, 0).into()),
+            server_config: tls.server_config,
+        });
+        let quic = match (&tls, quic_bind_addr) {
+            (Some(tls), Some(bind_addr)) => Some(QuicConfig {
+                server_config: tls.server_config.clone(),
+                bind_addr,
+            }),
+            _ => None,
+        };
+        let config = ServerConfig {
+            relay: Some(RelayConfig {
+                http_bind_addr: self.http_bind_addr,
+                tls,
+                limits: self.limits,
+                key_cache_capacity: self.key_cache_capacity,
+                access: self.access,
+                mesh: None,
+                admin_bind_addr: None,
+            }),
+            quic,
+            ..Default::default()
+        };
+        let server = Server::spawn(config).await?;
+        let url = match server.https_addr() {
+            Some(addr) => format!("https://{addr}"),
+            None => format!("http://{}", server.http_addr().expect("relay configured")),
+        };
+        let url = url.parse().expect("valid relay url");
+        Ok(RelayServer { server, url })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use n0_snafu::{Result, ResultExt};
+    use n0_watcher::Watcher;
+    use tracing_test::traced_test;
+
+    use super::*;
+    use crate::{Endpoint, RelayMode};
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_relay_server_builder() -> Result {
+        let (certs, server_config) =
+            iroh_relay::server::testing::self_signed_tls_certs_and_config();
+        let server = RelayServer::builder()
+            .tls(certs, server_config)
+            .quic((Ipv4Addr::LOCALHOST, 0).into())
+            .spawn()
+            .await?;
+        assert_eq!(server.url().scheme(), "https");
+        assert!(server.quic_addr().is_some());
+        assert!(server.relay_node().quic.is_some());
+
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Custom(server.relay_map()))
+            .insecure_skip_relay_cert_verify(true)
+            .bind()
+            .await?;
+        let home_relay =
+            tokio::time::timeout(Duration::from_secs(10), ep.home_relay().initialized())
+                .await
+                .context("timeout")??;
+        assert_eq!(home_relay, server.url());
+
+        ep.close().await;
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relay_server_quic_without_tls() {
+        let res = RelayServer::builder()
+            .quic((Ipv4Addr::LOCALHOST, 0).into())
+            .spawn()
+            .await;
+        assert!(matches!(res, Err(RelayServerError::QuicWithoutTls { .. })));
+    }
+}
@@ -60,6 +60,8 @@ pub async fn run_relay_server_with(quic: bool) -> Result<(RelayMap, RelayUrl, Se
             limits: Default::default(),
             key_cache_capacity: Some(1024),
             access: AccessConfig::Everyone,
+            mesh: None,
+            admin_bind_addr: None,
         }),
         quic,
         ..Default::default()